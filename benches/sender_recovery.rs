@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use criterion::*;
+use ethui_indexer::{
+    config::Config,
+    sync::{BlockSource, RethProviderFactory},
+};
+
+/// Recovers each sender from its transaction's signature
+async fn recover(source: &RethProviderFactory, from: u64, blocks: u64) -> Result<()> {
+    for number in from..from + blocks {
+        for tx in source.transactions(number).await? {
+            let _sender = tx.recover_signer();
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads senders in bulk from the `TransactionSenders` table
+async fn read(source: &RethProviderFactory, from: u64, blocks: u64) -> Result<()> {
+    for number in from..from + blocks {
+        let _senders = source.senders(number).await?;
+    }
+
+    Ok(())
+}
+
+/// Determines the senders of 1000 blocks, either by recovering signatures or by reading
+/// reth's sender table
+fn sender_recovery(c: &mut Criterion) {
+    let config = Config::read_from(&PathBuf::from("benches/ethui-indexer.toml")).unwrap();
+    let chain = &config.chains[0];
+    let source = RethProviderFactory::new(chain).unwrap();
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("sender_recovery");
+    group.sample_size(10);
+    let blocks = 1000;
+    let from = chain.start_block - blocks;
+    group.throughput(Throughput::Elements(blocks));
+
+    group.bench_function("recover", |b| {
+        b.to_async(&rt).iter(|| recover(&source, from, blocks))
+    });
+    group.bench_function("senders_table", |b| {
+        b.to_async(&rt).iter(|| read(&source, from, blocks))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, sender_recovery);
+criterion_main!(benches);
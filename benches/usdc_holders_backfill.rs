@@ -1,12 +1,12 @@
 mod utils;
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use color_eyre::Result;
 use criterion::*;
 use diesel::{
     sql_query,
-    sql_types::{Array, Bytea, Integer},
+    sql_types::{Array, BigInt, Bytea, Integer},
     RunQueryDsl,
 };
 use ethui_indexer::{
@@ -21,9 +21,11 @@ use self::utils::one_time_setup;
 /// truncates DB
 /// seeds 1000 initial users
 /// and creates a set of backfill jobs
-fn setup(concurrency: usize, jobs: u64, job_size: u64) -> Result<Config> {
+fn setup(concurrency: usize, jobs: u64, job_size: u64, logs_bloom: bool) -> Result<Config> {
     let (mut config, mut conn) = utils::setup("benches/ethui-indexer.toml")?;
     config.sync.backfill_concurrency = concurrency;
+    config.sync.adaptive_concurrency.enabled = false;
+    config.sync.logs_bloom = logs_bloom;
 
     let addresses: Vec<Address> =
         std::fs::read_to_string("benches/datasets/sepolia-usdc-holders.txt")?
@@ -32,16 +34,18 @@ fn setup(concurrency: usize, jobs: u64, job_size: u64) -> Result<Config> {
             .map(|l| Address(l.parse().unwrap()))
             .collect();
 
+    let chain = &config.chains[0];
+
     // create N non-overlapping jobs
     for i in 0..jobs {
         // the "+ 1" ensures each job is non-adjacent and does not reorg into a single large block
-        let start_block = config.chain.start_block as i32 - i as i32 * (job_size as i32 * 2);
+        let start_block = chain.start_block as i64 - i as i64 * (job_size as i64 * 2);
         sql_query(
             "INSERT INTO backfill_jobs (low, high, chain_id, addresses) VALUES ($1, $2, $3, $4)",
         )
-        .bind::<Integer, _>(start_block - job_size as i32)
-        .bind::<Integer, _>(start_block)
-        .bind::<Integer, _>(config.chain.chain_id)
+        .bind::<BigInt, _>(start_block - job_size as i64)
+        .bind::<BigInt, _>(start_block)
+        .bind::<Integer, _>(chain.chain_id)
         .bind::<Array<Bytea>, _>(&addresses[0..1])
         .execute(&mut conn)?;
     }
@@ -50,16 +54,23 @@ fn setup(concurrency: usize, jobs: u64, job_size: u64) -> Result<Config> {
 }
 
 async fn run(config: Config) -> Result<()> {
+    let chain = &config.chains[0];
     let (account_tx, _account_rx) = mpsc::unbounded_channel();
     let (job_tx, job_rx) = mpsc::unbounded_channel();
-    let db = Db::connect(&config, account_tx, job_tx).await?;
-    let chain = db.setup_chain(&config.chain).await?;
+    let db = Db::connect(
+        &config,
+        HashMap::from([(chain.chain_id, account_tx)]),
+        HashMap::from([(chain.chain_id, job_tx)]),
+    )
+    .await?;
+    db.setup_chain(chain).await?;
 
-    let provider_factory = Arc::new(RethProviderFactory::new(&config, &chain)?);
+    let source = Arc::new(RethProviderFactory::new(chain)?);
     let backfill = BackfillManager::new(
         db.clone(),
         &config,
-        provider_factory,
+        chain,
+        source,
         job_rx,
         StopStrategy::OnFinish,
     );
@@ -72,6 +83,7 @@ async fn run(config: Config) -> Result<()> {
 /// Processes a total of 100k blocks in different configurations:
 ///   - from 1 to 10000 concurrent jobs
 ///   - job size varies from 1 block to 1000 blocks per job
+///   - with and without skipping receipts based on the logs bloom
 fn backfill_1000jobsx1000blocks(c: &mut Criterion) {
     one_time_setup("benches/ethui-indexer.toml").unwrap();
 
@@ -82,21 +94,29 @@ fn backfill_1000jobsx1000blocks(c: &mut Criterion) {
     let job_size = 40;
     group.throughput(Throughput::Elements(jobs * job_size));
 
-    for concurrency in [1, 16, 32, 64, 128].iter() {
-        group.bench_with_input(
-            BenchmarkId::from_parameter(concurrency),
-            concurrency,
-            |b, concurrency| {
-                b.to_async(&rt).iter_batched(
-                    || {
-                        setup(*concurrency, jobs, job_size)
-                            .unwrap_or_else(|e| panic!("{}", e.to_string()))
-                    },
-                    |config| async move { run(config).await },
-                    BatchSize::LargeInput,
-                )
-            },
-        );
+    for logs_bloom in [false, true] {
+        let name = if logs_bloom {
+            "logs_bloom"
+        } else {
+            "all_receipts"
+        };
+
+        for concurrency in [1, 16, 32, 64, 128].iter() {
+            group.bench_with_input(
+                BenchmarkId::new(name, concurrency),
+                concurrency,
+                |b, concurrency| {
+                    b.to_async(&rt).iter_batched(
+                        || {
+                            setup(*concurrency, jobs, job_size, logs_bloom)
+                                .unwrap_or_else(|e| panic!("{}", e.to_string()))
+                        },
+                        |config| async move { run(config).await },
+                        BatchSize::LargeInput,
+                    )
+                },
+            );
+        }
     }
 
     group.finish();
@@ -6,7 +6,7 @@ use std::{env, path::PathBuf};
 use color_eyre::{eyre::eyre, Result};
 use diesel::{
     sql_query,
-    sql_types::{Bytea, Integer},
+    sql_types::{BigInt, Bytea, Integer},
     Connection, PgConnection, RunQueryDsl,
 };
 use diesel_migrations::MigrationHarness;
@@ -19,6 +19,7 @@ pub fn one_time_setup(config_file: &str) -> Result<()> {
     let url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
     let mut conn = PgConnection::establish(&url)?;
     let config = Config::read_from(&PathBuf::from(config_file))?;
+    let chain = &config.chains[0];
 
     conn.run_pending_migrations(MIGRATIONS)
         .map(|_| ())
@@ -28,9 +29,9 @@ pub fn one_time_setup(config_file: &str) -> Result<()> {
     sql_query("TRUNCATE TABLE chains CASCADE").execute(&mut conn)?;
 
     sql_query("INSERT INTO chains (chain_id, start_block, last_known_block) VALUES ($1, $2, $3)")
-        .bind::<Integer, _>(config.chain.chain_id)
-        .bind::<Integer, _>(0)
-        .bind::<Integer, _>(config.chain.start_block as i32)
+        .bind::<Integer, _>(chain.chain_id)
+        .bind::<BigInt, _>(0)
+        .bind::<BigInt, _>(chain.start_block as i64)
         .execute(&mut conn)?;
 
     let addresses: Vec<Address> =
@@ -43,7 +44,7 @@ pub fn one_time_setup(config_file: &str) -> Result<()> {
     for address in addresses.iter() {
         sql_query("INSERT INTO accounts (address, chain_id) VALUES ($1, $2)")
             .bind::<Bytea, _>(address)
-            .bind::<Integer, _>(config.chain.chain_id)
+            .bind::<Integer, _>(chain.chain_id)
             .execute(&mut conn)
             .unwrap();
     }
@@ -0,0 +1,8 @@
+CREATE TABLE blocks (
+  chain_id INTEGER NOT NULL,
+  number INTEGER NOT NULL,
+  hash BYTEA NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chain_id, number),
+  FOREIGN KEY (chain_id) REFERENCES chains (chain_id)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE chains
+  DROP COLUMN safe_block,
+  DROP COLUMN finalized_block;
@@ -0,0 +1,3 @@
+ALTER TABLE chains
+  ADD COLUMN safe_block INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN finalized_block INTEGER NOT NULL DEFAULT 0;
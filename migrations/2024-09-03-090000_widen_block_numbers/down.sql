@@ -0,0 +1,15 @@
+ALTER TABLE blocks
+  ALTER COLUMN number TYPE INTEGER;
+
+ALTER TABLE backfill_jobs
+  ALTER COLUMN low TYPE INTEGER,
+  ALTER COLUMN high TYPE INTEGER;
+
+ALTER TABLE txs
+  ALTER COLUMN block_number TYPE INTEGER;
+
+ALTER TABLE chains
+  ALTER COLUMN start_block TYPE INTEGER,
+  ALTER COLUMN last_known_block TYPE INTEGER,
+  ALTER COLUMN safe_block TYPE INTEGER,
+  ALTER COLUMN finalized_block TYPE INTEGER;
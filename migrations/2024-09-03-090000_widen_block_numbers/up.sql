@@ -0,0 +1,15 @@
+ALTER TABLE chains
+  ALTER COLUMN start_block TYPE BIGINT,
+  ALTER COLUMN last_known_block TYPE BIGINT,
+  ALTER COLUMN safe_block TYPE BIGINT,
+  ALTER COLUMN finalized_block TYPE BIGINT;
+
+ALTER TABLE txs
+  ALTER COLUMN block_number TYPE BIGINT;
+
+ALTER TABLE backfill_jobs
+  ALTER COLUMN low TYPE BIGINT,
+  ALTER COLUMN high TYPE BIGINT;
+
+ALTER TABLE blocks
+  ALTER COLUMN number TYPE BIGINT;
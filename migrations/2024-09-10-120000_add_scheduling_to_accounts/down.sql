@@ -0,0 +1,3 @@
+ALTER TABLE accounts
+  DROP COLUMN last_auth_at,
+  DROP COLUMN history_requested_at;
@@ -0,0 +1,3 @@
+ALTER TABLE accounts
+  ADD COLUMN last_auth_at TIMESTAMP,
+  ADD COLUMN history_requested_at TIMESTAMP;
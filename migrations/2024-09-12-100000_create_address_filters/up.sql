@@ -0,0 +1,9 @@
+CREATE TABLE address_filters (
+  chain_id INTEGER NOT NULL,
+  accounts_count BIGINT NOT NULL,
+  accounts_latest TIMESTAMP,
+  filter BYTEA NOT NULL,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chain_id),
+  FOREIGN KEY (chain_id) REFERENCES chains (chain_id)
+);
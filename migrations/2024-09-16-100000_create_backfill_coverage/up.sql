@@ -0,0 +1,14 @@
+CREATE TABLE backfill_coverage (
+  id SERIAL NOT NULL,
+  chain_id INTEGER NOT NULL,
+  address BYTEA NOT NULL,
+  method TEXT NOT NULL,
+  low BIGINT NOT NULL,
+  high BIGINT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (id),
+  FOREIGN KEY (chain_id) REFERENCES chains (chain_id)
+);
+
+CREATE INDEX backfill_coverage_address ON backfill_coverage (chain_id, address);
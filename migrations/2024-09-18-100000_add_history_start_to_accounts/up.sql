@@ -0,0 +1,2 @@
+ALTER TABLE accounts
+  ADD COLUMN history_start BIGINT;
@@ -0,0 +1,2 @@
+ALTER TABLE accounts
+  ADD COLUMN deployment_checked BOOLEAN NOT NULL DEFAULT FALSE;
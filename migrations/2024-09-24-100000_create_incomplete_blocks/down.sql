@@ -0,0 +1 @@
+DROP TABLE incomplete_blocks;
@@ -0,0 +1,7 @@
+CREATE TABLE incomplete_blocks (
+  chain_id INTEGER NOT NULL,
+  number BIGINT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chain_id, number),
+  FOREIGN KEY (chain_id) REFERENCES chains (chain_id)
+);
@@ -1,8 +1,8 @@
-use std::str::FromStr as _;
+use std::{collections::HashMap, str::FromStr as _};
 
 use axum::{
-    extract::{MatchedPath, State},
-    http::Request,
+    extract::{MatchedPath, Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Request},
     middleware::from_extractor,
     response::IntoResponse,
     routing::{get, post},
@@ -22,6 +22,9 @@ use super::{
     error::{ApiError, ApiResult},
     registration::RegistrationProof,
 };
+use crate::{config::BackfillPolicy, db::types::Finality, sync::block_at_timestamp};
+
+const UNAVAILABLE_BLOCKS: HeaderName = HeaderName::from_static("x-unavailable-blocks");
 
 pub fn app(jwt_secret: String, state: AppState) -> Router {
     let encoding_key = EncodingKey::from_secret(jwt_secret.as_ref());
@@ -30,10 +33,12 @@ pub fn app(jwt_secret: String, state: AppState) -> Router {
     let protected_routes = Router::new()
         .route("/test", post(test))
         .route("/history", post(history))
+        .route("/history_start", post(history_start))
         .route_layer(from_extractor::<Claims>());
 
     let public_routes = Router::new()
         .route("/health", get(health))
+        .route("/backfill_status", get(backfill_status))
         .route("/is_whitelisted", get(is_whitelisted))
         .route("/auth", post(auth))
         .route("/register", post(register));
@@ -66,19 +71,150 @@ pub fn app(jwt_secret: String, state: AppState) -> Router {
 
 async fn health() -> impl IntoResponse {}
 
+// GET /api/backfill_status
+pub async fn backfill_status(State(state): State<AppState>) -> impl IntoResponse {
+    let status: HashMap<_, _> = state
+        .backfill
+        .iter()
+        .map(|(chain_id, concurrency)| (chain_id, concurrency.status()))
+        .collect();
+
+    Json(json!(status))
+}
+
 pub async fn test(State(_state): State<AppState>) -> impl IntoResponse {
     Json(json!({"foo": "bar"}))
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryQuery {
+    chain_id: i32,
+
+    /// Leave out matches that are not at least this final
+    #[serde(default)]
+    min_finality: Finality,
+}
+
+// POST /api/history
 pub async fn history(
     State(state): State<AppState>,
     Claims { sub: address, .. }: Claims,
+    Query(query): Query<HistoryQuery>,
 ) -> ApiResult<impl IntoResponse> {
     let addr = alloy_primitives::Address::from_str(&format!("0x{:x}", address)).unwrap();
 
-    let history = state.db.history(&addr.into()).await?;
+    // under the lazy policy, this is what starts the backfill
+    if state.config.sync.backfill_policy == BackfillPolicy::Lazy {
+        state
+            .db
+            .request_history(query.chain_id, &addr.into())
+            .await?;
+    }
+
+    let history = state
+        .db
+        .history(query.chain_id, &addr.into(), query.min_finality)
+        .await?;
 
-    Ok(Json(json!(history)))
+    Ok((
+        unavailable_blocks(&state, query.chain_id).await?,
+        Json(json!(history)),
+    ))
+}
+
+/// Blocks that couldn't be fully searched, as a header listing inclusive ranges
+/// Those whose receipts the node pruned are never searched. Those searched with some of their
+/// receipts missing may lack matches by log topic
+/// e.g.: `x-unavailable-blocks: 1-12345, 13000-13002`
+async fn unavailable_blocks(state: &AppState, chain_id: i32) -> ApiResult<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let Some(chain) = state.config.chain(chain_id) else {
+        return Ok(headers);
+    };
+
+    let mut ranges = Vec::new();
+    let mut searched_from = chain.start_block;
+    if let Some(source) = state.sources.get(&chain_id) {
+        if let Some(last) = source.pruned().await?.receipts {
+            if last >= chain.start_block {
+                ranges.push((chain.start_block, last));
+                searched_from = last + 1;
+            }
+        }
+    }
+    for (low, high) in state.db.get_incomplete_ranges(chain_id).await? {
+        if high >= searched_from {
+            ranges.push((low.max(searched_from), high));
+        }
+    }
+
+    if !ranges.is_empty() {
+        let ranges: Vec<_> = ranges
+            .iter()
+            .map(|(low, high)| format!("{}-{}", low, high))
+            .collect();
+        headers.insert(
+            UNAVAILABLE_BLOCKS,
+            HeaderValue::from_str(&ranges.join(", ")).unwrap(),
+        );
+    }
+
+    Ok(headers)
+}
+
+/// Where an address's history starts, either as a block number or as a unix timestamp
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryStart {
+    Block(u64),
+    Timestamp(u64),
+}
+
+impl HistoryStart {
+    /// Resolves to a block number. A timestamp resolves to the first block mined at or after it
+    async fn resolve(self, chain_id: i32, state: &AppState) -> ApiResult<u64> {
+        let chain = state
+            .config
+            .chain(chain_id)
+            .ok_or(ApiError::UnsupportedChain(chain_id))?;
+
+        match self {
+            Self::Block(number) => Ok(number),
+            Self::Timestamp(timestamp) => {
+                let source = state
+                    .sources
+                    .get(&chain_id)
+                    .ok_or(ApiError::UnsupportedChain(chain_id))?;
+                let range = chain.start_block..source.tip().await? + 1;
+                Ok(block_at_timestamp(&**source, timestamp, range).await?)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryStartRequest {
+    chain_id: i32,
+    start: HistoryStart,
+}
+
+// POST /api/history_start
+// Only moves the start back. The blocks between the new start and the previous one are backfilled
+pub async fn history_start(
+    State(state): State<AppState>,
+    Claims { sub: address, .. }: Claims,
+    Json(request): Json<HistoryStartRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let addr = alloy_primitives::Address::from_str(&format!("0x{:x}", address)).unwrap();
+    let start_block = request.start.resolve(request.chain_id, &state).await?;
+
+    let start_block = state
+        .db
+        .extend_history(request.chain_id, &addr.into(), start_block)
+        .await?
+        .ok_or(ApiError::NotRegistered)?;
+
+    Ok(Json(json!({ "start_block": start_block })))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -100,7 +236,12 @@ pub async fn is_whitelisted(
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RegisterRequest {
     address: Address,
+    chain_id: i32,
     proof: RegistrationProof,
+
+    /// Where to start indexing the address's history. Defaults to the chain's `start_block`
+    #[serde(default)]
+    start: Option<HistoryStart>,
 }
 
 // POST /api/register
@@ -110,9 +251,24 @@ pub async fn register(
 ) -> ApiResult<impl IntoResponse> {
     let addr = reth_primitives::Address::from_str(&format!("0x{:x}", register.address)).unwrap();
 
-    register.proof.validate(addr, &state).await?;
+    if state.config.chain(register.chain_id).is_none() {
+        return Err(ApiError::UnsupportedChain(register.chain_id));
+    }
+
+    register
+        .proof
+        .validate(addr, register.chain_id, &state)
+        .await?;
 
-    state.db.register(register.address.into()).await?;
+    let start_block = match register.start {
+        Some(start) => Some(start.resolve(register.chain_id, &state).await?),
+        None => None,
+    };
+
+    state
+        .db
+        .register(register.chain_id, register.address.into(), start_block)
+        .await?;
 
     Ok(Json(json!({"result": "success"})))
 }
@@ -142,6 +298,7 @@ pub async fn auth(
     if !db.is_registered(auth.data.address.into()).await? {
         return Err(ApiError::NotRegistered);
     }
+    db.touch_auth(auth.data.address.into()).await?;
 
     let access_token = encode(&Header::default(), &Claims::from(auth.data), &encoding_key)?;
 
@@ -152,6 +309,7 @@ pub async fn auth(
 #[cfg(test)]
 mod test {
 
+    use alloy_primitives::B256;
     use axum::{
         body::Body,
         http::{Request, StatusCode},
@@ -167,14 +325,17 @@ mod test {
     use super::AuthRequest;
     use crate::{
         api::{
-            app::{AuthResponse, RegisterRequest},
+            app::{AuthResponse, HistoryStart, HistoryStartRequest, RegisterRequest},
             app_state::AppState,
             auth::IndexerAuth,
             registration::RegistrationProof,
             test_utils::{address, now, sign_typed_data, to_json_resp, wrong_address},
         },
         config::Config,
-        db::Db,
+        db::{
+            models::{CreateTx, IncompleteBlock},
+            Db,
+        },
     };
 
     fn get(uri: &str) -> Request<Body> {
@@ -227,7 +388,8 @@ mod test {
         let state = AppState {
             db,
             config,
-            provider_factory: None,
+            sources: Default::default(),
+            backfill: Default::default(),
         };
 
         super::app(jwt_secret, state)
@@ -242,7 +404,9 @@ mod test {
             "/api/register",
             RegisterRequest {
                 address,
+                chain_id: 31337,
                 proof: RegistrationProof::Test,
+                start: None,
             },
         );
         let resp = app.clone().oneshot(req).await?;
@@ -251,6 +415,26 @@ mod test {
         Ok(())
     }
 
+    #[rstest]
+    #[tokio::test]
+    #[serial]
+    async fn test_register_unsupported_chain(address: Address) -> Result<()> {
+        let app = build_app().await;
+        let req = post(
+            "/api/register",
+            RegisterRequest {
+                address,
+                chain_id: 1,
+                proof: RegistrationProof::Test,
+                start: None,
+            },
+        );
+        let resp = app.clone().oneshot(req).await?;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
     #[rstest]
     #[tokio::test]
     #[serial]
@@ -263,7 +447,9 @@ mod test {
             "/api/register",
             RegisterRequest {
                 address,
+                chain_id: 31337,
                 proof: RegistrationProof::Test,
+                start: None,
             },
         );
         app.clone().oneshot(registration).await?;
@@ -293,7 +479,9 @@ mod test {
             "/api/register",
             RegisterRequest {
                 address,
+                chain_id: 31337,
                 proof: RegistrationProof::Test,
+                start: None,
             },
         );
         app.clone().oneshot(registration).await?;
@@ -387,7 +575,9 @@ mod test {
             "/api/register",
             RegisterRequest {
                 address,
+                chain_id: 31337,
                 proof: RegistrationProof::Test,
+                start: None,
             },
         );
         app.clone().oneshot(registration).await?;
@@ -409,6 +599,163 @@ mod test {
         Ok(())
     }
 
+    #[rstest]
+    #[tokio::test]
+    #[serial]
+    async fn test_history_min_finality(address: Address, now: u64) -> Result<()> {
+        let app = build_app().await;
+        let db = Db::connect_test().await?;
+        let config = Config::for_test();
+        let chain_id = config.chains[0].chain_id;
+        db.setup_chain(&config.chains[0]).await?;
+        db.update_chain_finality(chain_id as u64, 20, 10).await?;
+
+        let registration = post(
+            "/api/register",
+            RegisterRequest {
+                address,
+                chain_id: 31337,
+                proof: RegistrationProof::Test,
+                start: None,
+            },
+        );
+        app.clone().oneshot(registration).await?;
+
+        let txs = [5, 15, 25]
+            .into_iter()
+            .map(|block_number| CreateTx {
+                address: address.into(),
+                chain_id,
+                hash: B256::repeat_byte(block_number as u8).into(),
+                block_number,
+            })
+            .collect();
+        db.create_txs(txs).await?;
+
+        let data = IndexerAuth::new(address, now + 20);
+        let req = post(
+            "/api/auth",
+            AuthRequest {
+                signature: sign_typed_data(&data).await?.to_string(),
+                data,
+            },
+        );
+        let jwt: AuthResponse = to_json_resp(app.clone().oneshot(req).await?).await?;
+
+        let req = post_with_jwt(
+            "/api/history?chain_id=31337&min_finality=safe",
+            jwt.access_token,
+            (),
+        );
+        let resp: serde_json::Value = to_json_resp(app.oneshot(req).await?).await?;
+        let finalities: Vec<_> = resp
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tx| tx["finality"].as_str().unwrap())
+            .collect();
+        assert_eq!(finalities, vec!["finalized", "safe"]);
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[serial]
+    async fn test_history_unavailable_blocks(address: Address, now: u64) -> Result<()> {
+        let app = build_app().await;
+        let db = Db::connect_test().await?;
+        let config = Config::for_test();
+        let chain_id = config.chains[0].chain_id;
+        db.setup_chain(&config.chains[0]).await?;
+
+        let registration = post(
+            "/api/register",
+            RegisterRequest {
+                address,
+                chain_id,
+                proof: RegistrationProof::Test,
+                start: None,
+            },
+        );
+        app.clone().oneshot(registration).await?;
+
+        let incomplete = [3, 4, 5, 9]
+            .into_iter()
+            .map(|number| IncompleteBlock { chain_id, number })
+            .collect();
+        db.create_incomplete_blocks(incomplete).await?;
+
+        let data = IndexerAuth::new(address, now + 20);
+        let req = post(
+            "/api/auth",
+            AuthRequest {
+                signature: sign_typed_data(&data).await?.to_string(),
+                data,
+            },
+        );
+        let jwt: AuthResponse = to_json_resp(app.clone().oneshot(req).await?).await?;
+
+        let req = post_with_jwt("/api/history?chain_id=31337", jwt.access_token, ());
+        let resp = app.oneshot(req).await?;
+        assert_eq!(
+            resp.headers().get("x-unavailable-blocks").unwrap(),
+            "3-5, 9-9"
+        );
+
+        Ok(())
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[serial]
+    async fn test_history_start(address: Address, now: u64) -> Result<()> {
+        let app = build_app().await;
+        let db = Db::connect_test().await?;
+        let config = Config::for_test();
+        let chain_id = config.chains[0].chain_id;
+        db.setup_chain(&config.chains[0]).await?;
+        db.update_chain(chain_id as u64, 200).await?;
+
+        let registration = post(
+            "/api/register",
+            RegisterRequest {
+                address,
+                chain_id,
+                proof: RegistrationProof::Test,
+                start: Some(HistoryStart::Block(100)),
+            },
+        );
+        app.clone().oneshot(registration).await?;
+
+        let data = IndexerAuth::new(address, now + 20);
+        let req = post(
+            "/api/auth",
+            AuthRequest {
+                signature: sign_typed_data(&data).await?.to_string(),
+                data,
+            },
+        );
+        let jwt: AuthResponse = to_json_resp(app.clone().oneshot(req).await?).await?;
+
+        let req = post_with_jwt(
+            "/api/history_start",
+            jwt.access_token,
+            HistoryStartRequest {
+                chain_id,
+                start: HistoryStart::Block(50),
+            },
+        );
+        let resp: serde_json::Value = to_json_resp(app.oneshot(req).await?).await?;
+        assert_eq!(resp["start_block"].as_u64(), Some(50));
+
+        let jobs = db.get_backfill_jobs(chain_id).await?;
+        let ranges: Vec<_> = jobs.iter().map(|j| (j.low, j.high)).collect();
+        assert_eq!(ranges, vec![(50, 100)]);
+
+        Ok(())
+    }
+
     #[rstest]
     #[tokio::test]
     #[serial]
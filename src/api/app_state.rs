@@ -1,10 +1,17 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
-use crate::{config::Config, db::Db, sync::RethProviderFactory};
+use crate::{
+    config::Config,
+    db::Db,
+    sync::{AdaptiveConcurrency, BlockSource},
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Db,
     pub config: Config,
-    pub provider_factory: Option<Arc<RethProviderFactory>>,
+    /// Block sources for each indexed chain, by chain ID
+    pub sources: HashMap<i32, Arc<dyn BlockSource>>,
+    /// Backfill concurrency controllers for each indexed chain, by chain ID
+    pub backfill: HashMap<i32, Arc<AdaptiveConcurrency>>,
 }
@@ -11,6 +11,9 @@ pub enum ApiError {
     #[error("Not Registered")]
     NotRegistered,
 
+    #[error("Unsupported chain {0}")]
+    UnsupportedChain(i32),
+
     #[error(transparent)]
     Jsonwebtoken(#[from] jsonwebtoken::errors::Error),
 
@@ -26,6 +29,7 @@ impl IntoResponse for ApiError {
             ApiError::NotRegistered | ApiError::InvalidCredentials | ApiError::Jsonwebtoken(_) => {
                 StatusCode::UNAUTHORIZED
             }
+            ApiError::UnsupportedChain(_) => StatusCode::BAD_REQUEST,
             ApiError::Unknown(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
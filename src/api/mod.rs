@@ -5,20 +5,25 @@ mod error;
 mod registration;
 mod test_utils;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
 use tokio::task::JoinHandle;
 use tracing::instrument;
 
 use self::{app::app, app_state::AppState};
-use crate::{config::Config, db::Db, sync::RethProviderFactory};
+use crate::{
+    config::Config,
+    db::Db,
+    sync::{AdaptiveConcurrency, BlockSource},
+};
 
 #[allow(clippy::async_yields_async)]
-#[instrument(name = "api", skip(db, config, provider_factory), fields(port = config.http.clone().unwrap().port))]
+#[instrument(name = "api", skip(db, config, sources, backfill), fields(port = config.http.clone().unwrap().port))]
 pub async fn start(
     db: Db,
     config: Config,
-    provider_factory: Arc<RethProviderFactory>,
+    sources: HashMap<i32, Arc<dyn BlockSource>>,
+    backfill: HashMap<i32, Arc<AdaptiveConcurrency>>,
 ) -> JoinHandle<Result<(), std::io::Error>> {
     let http_config = config.http.clone().unwrap();
 
@@ -28,7 +33,8 @@ pub async fn start(
     let state = AppState {
         db,
         config,
-        provider_factory: Some(provider_factory),
+        sources,
+        backfill,
     };
     let app = app(http_config.jwt_secret(), state);
 
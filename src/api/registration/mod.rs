@@ -1,6 +1,5 @@
 use color_eyre::{eyre::eyre, Result};
 use reth_primitives::{Address, TransactionSigned, TxHash};
-use reth_provider::TransactionsProvider as _;
 use serde::{Deserialize, Serialize};
 
 use super::app_state::AppState;
@@ -17,7 +16,7 @@ pub enum RegistrationProof {
 
 #[allow(unused)]
 impl RegistrationProof {
-    pub async fn validate(&self, address: Address, state: &AppState) -> Result<()> {
+    pub async fn validate(&self, address: Address, chain_id: i32, state: &AppState) -> Result<()> {
         match self {
             Self::Whitelist => {
                 if !state.config.whitelist.is_whitelisted(&address) {
@@ -26,8 +25,11 @@ impl RegistrationProof {
             }
 
             Self::TxHash(hash) => {
-                let provider = state.provider_factory.clone().unwrap().get()?;
-                match provider.transaction_by_hash(*hash)? {
+                let source = state
+                    .sources
+                    .get(&chain_id)
+                    .ok_or_else(|| eyre!("Unsupported chain {}", chain_id))?;
+                match source.transaction_by_hash(*hash).await? {
                     Some(tx) => self.validate_tx(address, state, &tx)?,
                     None => return Err(eyre!("Transaction not found")),
                 }
@@ -22,8 +22,7 @@ struct Args {
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct Config {
-    pub reth: RethConfig,
-    pub chain: ChainConfig,
+    pub chains: Vec<ChainConfig>,
     pub sync: SyncConfig,
 
     #[serde(default)]
@@ -38,6 +37,25 @@ pub struct Config {
 pub struct RethConfig {
     pub db: PathBuf,
     pub static_files: PathBuf,
+
+    /// reth's IPC socket, through which new blocks are notified as soon as they are added
+    /// Otherwise, the datadir is watched for changes
+    #[serde(default)]
+    pub ipc: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RpcConfig {
+    /// HTTP JSON-RPC endpoint of the node
+    pub url: String,
+
+    /// How many blocks to fetch in a single batch request
+    #[serde(default = "default_rpc_batch_size")]
+    pub batch_size: u64,
+
+    /// How many fetched blocks to keep in memory
+    #[serde(default = "default_rpc_cache_size")]
+    pub cache_size: usize,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -45,6 +63,28 @@ pub struct ChainConfig {
     pub chain_id: i32,
     #[serde(default = "default_from_block")]
     pub start_block: u64,
+
+    /// Read blocks directly from a local reth database
+    #[serde(default)]
+    pub reth: Option<RethConfig>,
+
+    /// Read blocks from a JSON-RPC node instead. Exactly one of `reth` or `rpc` must be set
+    #[serde(default)]
+    pub rpc: Option<RpcConfig>,
+
+    /// Name of a built-in reth chain spec (e.g.: `holesky`, `dev`)
+    /// If neither this nor `genesis` are given, the spec is inferred from `chain_id`
+    #[serde(default)]
+    pub spec: Option<String>,
+
+    /// Path to a genesis JSON file, for chains without a built-in spec
+    #[serde(default)]
+    pub genesis: Option<PathBuf>,
+
+    /// Directory of Era1 archive files
+    /// Backfill reads the blocks they cover from there, sparing the node
+    #[serde(default)]
+    pub era1: Option<PathBuf>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -52,8 +92,129 @@ pub struct SyncConfig {
     #[serde(default = "default_buffer_size")]
     pub buffer_size: usize,
 
+    /// Initial number of concurrent backfill workers
     #[serde(default = "default_backfill_concurrency")]
     pub backfill_concurrency: usize,
+
+    /// Bounds within which `backfill_concurrency` is adjusted at runtime
+    #[serde(default)]
+    pub adaptive_concurrency: AdaptiveConcurrencyConfig,
+
+    /// How many recent block hashes to keep for reorg detection
+    #[serde(default = "default_reorg_depth")]
+    pub reorg_depth: u64,
+
+    /// How many blocks behind the tip a block is considered safe
+    #[serde(default = "default_safe_depth")]
+    pub safe_depth: u64,
+
+    /// How often to check for a new tip, when the source can't notify of it
+    #[serde(default = "default_tip_poll_ms")]
+    pub tip_poll_ms: u64,
+
+    /// Check each block's logs bloom before reading its receipts,
+    /// skipping them if no address can be a log topic
+    #[serde(default = "default_logs_bloom")]
+    pub logs_bloom: bool,
+
+    /// How many consecutive blocks a backfill worker reads at once
+    #[serde(default = "default_backfill_batch_size")]
+    pub backfill_batch_size: u64,
+
+    /// Backfill jobs are split so that idle workers can share them, but never into sub-ranges
+    /// smaller than this many blocks
+    #[serde(default = "default_min_job_size")]
+    pub min_job_size: u64,
+
+    /// Backfill jobs spanning more than this many blocks are cut into smaller ones
+    #[serde(default = "default_max_job_size")]
+    pub max_job_size: u64,
+
+    /// Parallelism of each stage of a backfill worker
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
+
+    /// When backfill jobs are allowed to start
+    #[serde(default)]
+    pub backfill_policy: BackfillPolicy,
+
+    /// How backfill workers find the blocks to search
+    #[serde(default)]
+    pub backfill_method: BackfillMethod,
+}
+
+/// Backfill jobs of recently authenticated users always run first, newest blocks first
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackfillPolicy {
+    /// Start backfilling as soon as an address is registered
+    #[default]
+    Eager,
+
+    /// Only start backfilling an address the first time its history is requested
+    Lazy,
+}
+
+/// Backfill concurrency is adjusted every `interval_secs`, towards whatever value gives the best
+/// measured throughput
+#[derive(Deserialize, Clone, Debug)]
+pub struct AdaptiveConcurrencyConfig {
+    #[serde(default = "default_adaptive_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "default_adaptive_min")]
+    pub min: usize,
+
+    #[serde(default = "default_adaptive_max")]
+    pub max: usize,
+
+    #[serde(default = "default_adaptive_interval_secs")]
+    pub interval_secs: u64,
+}
+
+/// Backfill workers read, match and write blocks in concurrent stages, connected by bounded
+/// queues, so that IO and CPU overlap
+#[derive(Deserialize, Clone, Debug)]
+pub struct PipelineConfig {
+    /// How many block batches to read concurrently
+    #[serde(default = "default_pipeline_readers")]
+    pub readers: usize,
+
+    /// How many block batches to match concurrently
+    #[serde(default = "default_pipeline_matchers")]
+    pub matchers: usize,
+
+    /// How many DB inserts to run concurrently
+    #[serde(default = "default_pipeline_writers")]
+    pub writers: usize,
+
+    /// How many batches a stage may get ahead of the next one
+    #[serde(default = "default_pipeline_queue_size")]
+    pub queue_size: usize,
+}
+
+/// How backfill workers find the blocks to search, ordered from most to least thorough
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum BackfillMethod {
+    /// Search every block
+    #[default]
+    Full,
+
+    /// Only search blocks in which the node's account history index shows a nonce or balance
+    /// change for one of the job's addresses, plus those whose logs bloom may match them
+    /// Much cheaper, but misses value-less calls to an address that don't log it as a topic
+    /// Ranges without an index (e.g.: Era1 archives, JSON-RPC) are searched in full
+    HistoryIndex,
+}
+
+impl BackfillMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::HistoryIndex => "history_index",
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -70,6 +231,28 @@ pub struct PaymentConfig {
     pub min_amount: alloy_primitives::U256,
 }
 
+impl Default for AdaptiveConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_adaptive_enabled(),
+            min: default_adaptive_min(),
+            max: default_adaptive_max(),
+            interval_secs: default_adaptive_interval_secs(),
+        }
+    }
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            readers: default_pipeline_readers(),
+            matchers: default_pipeline_matchers(),
+            writers: default_pipeline_writers(),
+            queue_size: default_pipeline_queue_size(),
+        }
+    }
+}
+
 impl HttpConfig {
     pub fn jwt_secret(&self) -> String {
         std::env::var(&self.jwt_secret_env).expect("JWT secret not set")
@@ -85,7 +268,21 @@ impl Config {
     pub fn read() -> Result<Self> {
         let args = Args::parse();
 
-        let mut config = Self::read_from(args.config.as_path())?;
+        Self::load(args.config.as_path())
+    }
+
+    /// Reads the config file given by `ETHUI_INDEXER_CONFIG`, without parsing CLI arguments
+    /// Used when running inside reth, whose CLI owns the process arguments
+    #[cfg(feature = "exex")]
+    pub fn read_from_env() -> Result<Self> {
+        let path = std::env::var("ETHUI_INDEXER_CONFIG")
+            .unwrap_or_else(|_| "ethui-indexer.toml".to_owned());
+
+        Self::load(Path::new(&path))
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let mut config = Self::read_from(path)?;
         config.whitelist.preload()?;
 
         Ok(config)
@@ -94,6 +291,11 @@ impl Config {
     pub fn read_from(path: &Path) -> Result<Self> {
         Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
     }
+
+    /// Finds the configuration for a given chain, if it is being indexed
+    pub fn chain(&self, chain_id: i32) -> Option<&ChainConfig> {
+        self.chains.iter().find(|c| c.chain_id == chain_id)
+    }
 }
 
 impl Default for HttpConfig {
@@ -121,21 +323,105 @@ fn default_backfill_concurrency() -> usize {
     10
 }
 
+fn default_adaptive_enabled() -> bool {
+    true
+}
+
+fn default_adaptive_min() -> usize {
+    1
+}
+
+fn default_adaptive_max() -> usize {
+    256
+}
+
+fn default_adaptive_interval_secs() -> u64 {
+    5
+}
+
+fn default_min_job_size() -> u64 {
+    10_000
+}
+
+fn default_max_job_size() -> u64 {
+    1_000_000
+}
+
+fn default_pipeline_readers() -> usize {
+    2
+}
+
+fn default_pipeline_matchers() -> usize {
+    2
+}
+
+fn default_pipeline_writers() -> usize {
+    1
+}
+
+fn default_pipeline_queue_size() -> usize {
+    4
+}
+
+fn default_backfill_batch_size() -> u64 {
+    100
+}
+
+fn default_logs_bloom() -> bool {
+    true
+}
+
+fn default_rpc_batch_size() -> u64 {
+    20
+}
+
+fn default_rpc_cache_size() -> usize {
+    512
+}
+
+fn default_reorg_depth() -> u64 {
+    64
+}
+
+fn default_safe_depth() -> u64 {
+    32
+}
+
+fn default_tip_poll_ms() -> u64 {
+    2000
+}
+
 #[cfg(test)]
 impl Config {
     pub fn for_test() -> Self {
         Self {
-            reth: RethConfig {
-                db: PathBuf::from("test-db"),
-                static_files: PathBuf::from("static"),
-            },
-            chain: ChainConfig {
+            chains: vec![ChainConfig {
                 chain_id: 31337,
                 start_block: 1,
-            },
+                reth: Some(RethConfig {
+                    db: PathBuf::from("test-db"),
+                    static_files: PathBuf::from("static"),
+                    ipc: None,
+                }),
+                rpc: None,
+                spec: None,
+                genesis: None,
+                era1: None,
+            }],
             sync: SyncConfig {
                 buffer_size: 1000,
                 backfill_concurrency: 10,
+                adaptive_concurrency: Default::default(),
+                reorg_depth: 64,
+                safe_depth: 32,
+                tip_poll_ms: 100,
+                logs_bloom: true,
+                backfill_batch_size: 100,
+                min_job_size: 10_000,
+                max_job_size: 1_000_000,
+                pipeline: Default::default(),
+                backfill_policy: Default::default(),
+                backfill_method: Default::default(),
             },
             http: None,
             db: DbConfig {
@@ -2,6 +2,11 @@ pub mod models;
 mod schema;
 pub mod types;
 
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
 use color_eyre::{eyre::eyre, Result};
 use diesel::{delete, insert_into, prelude::*, update};
 use diesel_async::{
@@ -15,16 +20,22 @@ use tokio::sync::mpsc::UnboundedSender;
 use tracing::instrument;
 
 use self::{
-    models::{Chain, CreateTx},
-    types::Address,
+    models::{
+        AccountSchedule, AccountsStamp, AddressFilter, Block, BlockRange, Chain, CreateTx,
+        IncompleteBlock, TxWithFinality,
+    },
+    types::{Address, Finality},
 };
 use crate::{
-    config::{ChainConfig, Config},
-    db::models::{BackfillJob, BackfillJobWithChainId, BackfillJobWithId},
+    config::{BackfillMethod, ChainConfig, Config},
+    db::models::{BackfillJob, BackfillJobWithChainId, BackfillJobWithId, Coverage},
 };
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
 
+/// Postgres accepts at most this many bind parameters in a single statement
+const MAX_BIND_PARAMS: usize = u16::MAX as usize;
+
 /// An abstract DB connection
 /// In production, `PgBackend` is meant to be used, but the trait allows for the existance of
 /// `InMemoryBackend` as well, which is useful for testing
@@ -33,23 +44,20 @@ pub struct Db {
     /// async db pool
     pool: Pool<AsyncPgConnection>,
 
-    /// notify sync job of new accounts
-    new_accounts_tx: Option<UnboundedSender<alloy_primitives::Address>>,
+    /// notify each chain's sync job of new accounts
+    new_accounts_tx: HashMap<i32, UnboundedSender<alloy_primitives::Address>>,
 
-    /// notify backfill job of new jobs
+    /// notify each chain's backfill job of new jobs
     /// (which are created from new accounts, but asynchronously, so need their own event)
     /// payload is empty because the job only needs a notification to rearrange from DB data
-    new_job_tx: Option<UnboundedSender<()>>,
-
-    /// chain ID we're running on
-    chain_id: i32,
+    new_job_tx: HashMap<i32, UnboundedSender<()>>,
 }
 
 impl Db {
     pub async fn connect(
         config: &Config,
-        new_accounts_tx: UnboundedSender<alloy_primitives::Address>,
-        new_job_tx: UnboundedSender<()>,
+        new_accounts_tx: HashMap<i32, UnboundedSender<alloy_primitives::Address>>,
+        new_job_tx: HashMap<i32, UnboundedSender<()>>,
     ) -> Result<Self> {
         Self::migrate(&config.db.url).await?;
 
@@ -59,9 +67,8 @@ impl Db {
 
         Ok(Self {
             pool,
-            new_accounts_tx: Some(new_accounts_tx),
-            new_job_tx: Some(new_job_tx),
-            chain_id: config.chain.chain_id,
+            new_accounts_tx,
+            new_job_tx,
         })
     }
 
@@ -74,9 +81,8 @@ impl Db {
 
         let res = Self {
             pool,
-            new_accounts_tx: None,
-            new_job_tx: None,
-            chain_id: 31337,
+            new_accounts_tx: HashMap::new(),
+            new_job_tx: HashMap::new(),
         };
 
         res.truncate().await?;
@@ -105,7 +111,18 @@ impl Db {
         use diesel::sql_query;
 
         let mut conn = self.pool.get().await?;
-        for table in ["accounts", "chains", "backfill_jobs", "txs"].iter() {
+        for table in [
+            "accounts",
+            "address_filters",
+            "backfill_coverage",
+            "chains",
+            "backfill_jobs",
+            "blocks",
+            "incomplete_blocks",
+            "txs",
+        ]
+        .iter()
+        {
             sql_query(format!("TRUNCATE TABLE {} CASCADE", table))
                 .execute(&mut conn)
                 .await
@@ -122,12 +139,13 @@ impl Db {
         use schema::chains::dsl::*;
 
         let mut conn = self.pool.get().await?;
+        let start = i64::try_from(chain.start_block)?;
 
         let res = insert_into(chains)
             .values((
                 chain_id.eq(chain.chain_id),
-                start_block.eq(chain.start_block as i32),
-                last_known_block.eq(chain.start_block as i32 - 1),
+                start_block.eq(start),
+                last_known_block.eq(start - 1),
             ))
             .on_conflict_do_nothing()
             .execute(&mut conn)
@@ -152,36 +170,459 @@ impl Db {
         let mut conn = self.pool.get().await?;
 
         let res = update(chains)
-            .filter(chain_id.eq(id as i32))
-            .set(last_known_block.eq(last_known as i32))
+            .filter(chain_id.eq(i32::try_from(id)?))
+            .set(last_known_block.eq(i64::try_from(last_known)?))
+            .execute(&mut conn)
+            .await;
+
+        handle_error(res).await
+    }
+
+    /// Loads the hashes of the most recently indexed blocks, newest first
+    #[instrument(skip(self))]
+    pub async fn get_recent_blocks(&self, chain_id: i32, limit: u64) -> Result<Vec<Block>> {
+        use schema::blocks::dsl;
+        let mut conn = self.pool.get().await?;
+
+        let res = dsl::blocks
+            .filter(dsl::chain_id.eq(chain_id))
+            .select(Block::as_select())
+            .order(dsl::number.desc())
+            .limit(i64::try_from(limit)?)
+            .load(&mut conn)
+            .await?;
+
+        Ok(res)
+    }
+
+    /// Stores the hashes of newly indexed blocks
+    /// and forgets those below `prune_below`, which are too deep to be reorged
+    #[instrument(skip(self, new_blocks), fields(blocks = new_blocks.len()))]
+    pub async fn create_blocks(
+        &self,
+        chain_id: i32,
+        new_blocks: Vec<Block>,
+        prune_below: u64,
+    ) -> Result<()> {
+        use diesel::upsert::excluded;
+        use schema::blocks::dsl;
+        let prune_below = i64::try_from(prune_below)?;
+        let mut conn = self.pool.get().await?;
+
+        // each row binds its chain_id, number and hash
+        for chunk in new_blocks.chunks(MAX_BIND_PARAMS / 3) {
+            insert_into(dsl::blocks)
+                .values(chunk)
+                .on_conflict((dsl::chain_id, dsl::number))
+                .do_update()
+                .set(dsl::hash.eq(excluded(dsl::hash)))
+                .execute(&mut conn)
+                .await?;
+        }
+
+        let res = delete(dsl::blocks)
+            .filter(dsl::chain_id.eq(chain_id))
+            .filter(dsl::number.lt(prune_below))
+            .execute(&mut conn)
+            .await;
+
+        handle_error(res).await
+    }
+
+    /// Records blocks that were searched without some of their receipts
+    #[instrument(skip(self, blocks), fields(blocks = blocks.len()))]
+    pub async fn create_incomplete_blocks(&self, blocks: Vec<IncompleteBlock>) -> Result<()> {
+        use schema::incomplete_blocks::dsl;
+        let mut conn = self.pool.get().await?;
+
+        // each row binds its chain_id and number
+        for chunk in blocks.chunks(MAX_BIND_PARAMS / 2) {
+            insert_into(dsl::incomplete_blocks)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(&mut conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Incomplete blocks of a chain, merged into ascending inclusive ranges
+    pub async fn get_incomplete_ranges(&self, chain_id: i32) -> Result<Vec<(u64, u64)>> {
+        use diesel::{sql_query, sql_types::Integer};
+        let mut conn = self.pool.get().await?;
+
+        // consecutive numbers share their difference with their row number
+        let res: Vec<BlockRange> = sql_query(
+            "SELECT MIN(number) AS low, MAX(number) AS high FROM ( \
+                SELECT number, number - ROW_NUMBER() OVER (ORDER BY number) AS island \
+                FROM incomplete_blocks WHERE chain_id = $1 \
+             ) numbered GROUP BY island ORDER BY low",
+        )
+        .bind::<Integer, _>(chain_id)
+        .load(&mut conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| Ok((u64::try_from(r.low)?, u64::try_from(r.high)?)))
+            .collect()
+    }
+
+    /// Reverts all data indexed above `fork_block`, after a chain reorganization
+    /// The forward sync resumes right after the fork point, for all addresses, so backfill jobs
+    /// only need to cover what is below it
+    #[instrument(skip(self))]
+    pub async fn rollback_chain(&self, chain_id: i32, fork_block: u64) -> Result<()> {
+        use schema::{backfill_coverage, backfill_jobs, blocks, chains, incomplete_blocks, txs};
+        let fork_block = i64::try_from(fork_block)?;
+        let mut conn = self.pool.get().await?;
+
+        conn.transaction::<_, diesel::result::Error, _>(|mut conn| {
+            async move {
+                delete(txs::table)
+                    .filter(txs::chain_id.eq(chain_id))
+                    .filter(txs::block_number.gt(fork_block))
+                    .execute(&mut conn)
+                    .await?;
+
+                delete(blocks::table)
+                    .filter(blocks::chain_id.eq(chain_id))
+                    .filter(blocks::number.gt(fork_block))
+                    .execute(&mut conn)
+                    .await?;
+
+                delete(incomplete_blocks::table)
+                    .filter(incomplete_blocks::chain_id.eq(chain_id))
+                    .filter(incomplete_blocks::number.gt(fork_block))
+                    .execute(&mut conn)
+                    .await?;
+
+                update(chains::table)
+                    .filter(chains::chain_id.eq(chain_id))
+                    .set(chains::last_known_block.eq(fork_block))
+                    .execute(&mut conn)
+                    .await?;
+
+                // jobs entirely above the fork are no longer needed
+                delete(backfill_jobs::table)
+                    .filter(backfill_jobs::chain_id.eq(chain_id))
+                    .filter(backfill_jobs::low.gt(fork_block))
+                    .execute(&mut conn)
+                    .await?;
+
+                // jobs crossing it are clamped to it
+                update(backfill_jobs::table)
+                    .filter(backfill_jobs::chain_id.eq(chain_id))
+                    .filter(backfill_jobs::high.gt(fork_block + 1))
+                    .set(backfill_jobs::high.eq(fork_block + 1))
+                    .execute(&mut conn)
+                    .await?;
+
+                // and so is what was already covered
+                delete(backfill_coverage::table)
+                    .filter(backfill_coverage::chain_id.eq(chain_id))
+                    .filter(backfill_coverage::low.gt(fork_block))
+                    .execute(&mut conn)
+                    .await?;
+                update(backfill_coverage::table)
+                    .filter(backfill_coverage::chain_id.eq(chain_id))
+                    .filter(backfill_coverage::high.gt(fork_block + 1))
+                    .set(backfill_coverage::high.eq(fork_block + 1))
+                    .execute(&mut conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        // backfill workers may be walking the reverted range. notify the manager so they restart
+        if let Some(tx) = self.new_job_tx.get(&chain_id) {
+            tx.send(())?;
+        }
+
+        Ok(())
+    }
+
+    /// Updates the last known safe and finalized blocks for a chain
+    #[instrument(skip(self, id))]
+    pub async fn update_chain_finality(&self, id: u64, safe: u64, finalized: u64) -> Result<()> {
+        use schema::chains::dsl::*;
+
+        let mut conn = self.pool.get().await?;
+
+        let res = update(chains)
+            .filter(chain_id.eq(i32::try_from(id)?))
+            .set((
+                safe_block.eq(i64::try_from(safe)?),
+                finalized_block.eq(i64::try_from(finalized)?),
+            ))
             .execute(&mut conn)
             .await;
 
         handle_error(res).await
     }
 
-    /// Register a new account
+    /// Registers an address, whose history starts at `start_block` if given, or at the chain's
+    /// `start_block` otherwise
+    /// If the address is a contract, the start is later moved up to its deployment, see
+    /// `raise_history_start`
+    /// Registering an address again with an earlier `start_block` extends its history there, see
+    /// `extend_history`
     #[instrument(skip(self))]
-    pub async fn register(&self, address: Address) -> Result<()> {
+    pub async fn register(
+        &self,
+        chain_id: i32,
+        address: Address,
+        start_block: Option<u64>,
+    ) -> Result<()> {
         use schema::accounts::dsl;
+        let history_start = start_block.map(i64::try_from).transpose()?;
 
         let mut conn = self.pool.get().await?;
 
         let res = insert_into(dsl::accounts)
-            .values((dsl::address.eq(&address), dsl::chain_id.eq(self.chain_id)))
+            .values((
+                dsl::address.eq(&address),
+                dsl::chain_id.eq(chain_id),
+                dsl::history_start.eq(history_start),
+            ))
             .on_conflict_do_nothing()
             .execute(&mut conn)
             .await;
 
-        // notify sync job if creation was successful
-        if let (Ok(_), Some(tx)) = (&res, &self.new_accounts_tx) {
-            tx.send(address.0)?;
+        match res {
+            // already registered, so only the missing part of its history is scheduled
+            Ok(0) => {
+                if let Some(start_block) = start_block {
+                    self.extend_history(chain_id, &address, start_block).await?;
+                }
+                Ok(())
+            }
+            // notify sync job if creation was successful
+            Ok(_) => {
+                if let Some(tx) = self.new_accounts_tx.get(&chain_id) {
+                    tx.send(address.0)?;
+                }
+                Ok(())
+            }
+            res => handle_error(res).await,
         }
+    }
 
-        handle_error(res).await
+    /// Block at which an account's history was requested to start, if one was given
+    pub async fn get_history_start(&self, chain_id: i32, address: &Address) -> Result<Option<u64>> {
+        use schema::accounts::dsl;
+        let mut conn = self.pool.get().await?;
+
+        let res: Option<Option<i64>> = dsl::accounts
+            .filter(dsl::chain_id.eq(chain_id))
+            .filter(dsl::address.eq(address))
+            .select(dsl::history_start)
+            .first(&mut conn)
+            .await
+            .optional()?;
+
+        Ok(res.flatten().map(u64::try_from).transpose()?)
+    }
+
+    /// Accounts whose contract deployment wasn't looked up yet, along with the start they
+    /// registered with, if any
+    pub async fn get_unchecked_deployments(
+        &self,
+        chain_id: i32,
+    ) -> Result<Vec<(Address, Option<u64>)>> {
+        use schema::accounts::dsl;
+        let mut conn = self.pool.get().await?;
+
+        let res: Vec<(Address, Option<i64>)> = dsl::accounts
+            .filter(dsl::chain_id.eq(chain_id))
+            .filter(dsl::deployment_checked.eq(false))
+            .select((dsl::address, dsl::history_start))
+            .load(&mut conn)
+            .await?;
+
+        res.into_iter()
+            .map(|(address, start)| Ok((address, start.map(u64::try_from).transpose()?)))
+            .collect()
+    }
+
+    /// Moves the start of an account's history up to `start_block`, once its deployment was found,
+    /// and marks its deployment as checked
+    /// Its backfill jobs stop short of the new start. Jobs shared with other addresses are split
+    /// there, so that only the others are searched below it
+    /// `None` leaves the start unchanged, for when the account isn't a contract or its deployment
+    /// couldn't be found
+    pub async fn raise_history_start(
+        &self,
+        chain_id: i32,
+        address: &Address,
+        start_block: Option<u64>,
+    ) -> Result<()> {
+        use schema::{accounts::dsl, backfill_jobs};
+        let start_block = start_block.map(i64::try_from).transpose()?;
+        let mut conn = self.pool.get().await?;
+
+        conn.transaction::<_, diesel::result::Error, _>(|mut conn| {
+            async move {
+                let query = update(dsl::accounts)
+                    .filter(dsl::chain_id.eq(chain_id))
+                    .filter(dsl::address.eq(address));
+                let Some(start) = start_block else {
+                    query
+                        .set(dsl::deployment_checked.eq(true))
+                        .execute(&mut conn)
+                        .await?;
+                    return Ok(());
+                };
+                query
+                    .set((
+                        dsl::history_start.eq(start),
+                        dsl::deployment_checked.eq(true),
+                    ))
+                    .execute(&mut conn)
+                    .await?;
+
+                let jobs = backfill_jobs::table
+                    .filter(backfill_jobs::chain_id.eq(chain_id))
+                    .filter(backfill_jobs::addresses.contains(vec![address.clone()]))
+                    .filter(backfill_jobs::low.lt(start))
+                    .select(BackfillJobWithId::as_select())
+                    .for_update()
+                    .load(&mut conn)
+                    .await?;
+
+                for job in jobs {
+                    delete(backfill_jobs::table)
+                        .filter(backfill_jobs::id.eq(job.id))
+                        .execute(&mut conn)
+                        .await?;
+
+                    let others: Vec<_> = job
+                        .addresses
+                        .iter()
+                        .filter(|a| a.0 != address.0)
+                        .cloned()
+                        .collect();
+                    let mut pieces = vec![];
+                    if !others.is_empty() {
+                        pieces.push((others, job.low, start.min(job.high)));
+                    }
+                    pieces.push((job.addresses, start.max(job.low), job.high));
+
+                    for (addresses, low, high) in pieces.into_iter().filter(|p| p.1 < p.2) {
+                        insert_into(backfill_jobs::table)
+                            .values((
+                                backfill_jobs::addresses.eq(addresses),
+                                backfill_jobs::chain_id.eq(chain_id),
+                                backfill_jobs::low.eq(low),
+                                backfill_jobs::high.eq(high),
+                            ))
+                            .on_conflict_do_nothing()
+                            .execute(&mut conn)
+                            .await?;
+                    }
+                }
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(())
     }
 
-    /// Checks if an account is registered
+    /// Moves the start of an account's history back to `start_block`, and schedules a backfill
+    /// job for the blocks between it and the previous start
+    /// A start later than the current one leaves it unchanged, since that history is already
+    /// indexed. An earlier one has the account's deployment looked up again, so that a contract's
+    /// history still doesn't reach before it
+    /// Returns the resulting start, or `None` if the account isn't registered
+    pub async fn extend_history(
+        &self,
+        chain_id: i32,
+        address: &Address,
+        start_block: u64,
+    ) -> Result<Option<u64>> {
+        use schema::{accounts::dsl, backfill_jobs, chains};
+        let start_block = i64::try_from(start_block)?;
+        let mut conn = self.pool.get().await?;
+
+        let res = conn
+            .transaction::<_, diesel::result::Error, _>(|mut conn| {
+                async move {
+                    let Some(current) = dsl::accounts
+                        .filter(dsl::chain_id.eq(chain_id))
+                        .filter(dsl::address.eq(address))
+                        .select(dsl::history_start)
+                        .for_update()
+                        .first::<Option<i64>>(&mut conn)
+                        .await
+                        .optional()?
+                    else {
+                        return Ok(None);
+                    };
+
+                    let (chain_start, last_known_block) = chains::table
+                        .filter(chains::chain_id.eq(chain_id))
+                        .select((chains::start_block, chains::last_known_block))
+                        .first::<(i64, i64)>(&mut conn)
+                        .await?;
+
+                    let current = current.unwrap_or(chain_start);
+                    let low = start_block.max(chain_start);
+                    if low >= current {
+                        return Ok(Some((current, false)));
+                    }
+
+                    update(dsl::accounts)
+                        .filter(dsl::chain_id.eq(chain_id))
+                        .filter(dsl::address.eq(address))
+                        .set((
+                            dsl::history_start.eq(low),
+                            dsl::deployment_checked.eq(false),
+                        ))
+                        .execute(&mut conn)
+                        .await?;
+
+                    // anything above the forward sync's position will be indexed by it
+                    let high = current.min(last_known_block + 1);
+                    if low >= high {
+                        return Ok(Some((low, false)));
+                    }
+
+                    insert_into(backfill_jobs::table)
+                        .values((
+                            backfill_jobs::addresses.eq(vec![address.clone()]),
+                            backfill_jobs::chain_id.eq(chain_id),
+                            backfill_jobs::low.eq(low),
+                            backfill_jobs::high.eq(high),
+                        ))
+                        .on_conflict_do_nothing()
+                        .execute(&mut conn)
+                        .await?;
+
+                    Ok(Some((low, true)))
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        let Some((start, scheduled)) = res else {
+            return Ok(None);
+        };
+
+        // notify backfill job new work is available
+        if let (true, Some(tx)) = (scheduled, self.new_job_tx.get(&chain_id)) {
+            tx.send(())?;
+        }
+
+        Ok(Some(u64::try_from(start)?))
+    }
+
+    /// Checks if an account is registered, on any chain
     #[instrument(skip(self))]
     pub async fn is_registered(&self, address: Address) -> Result<bool> {
         use schema::accounts::dsl;
@@ -190,7 +631,6 @@ impl Db {
 
         let res: i64 = schema::accounts::table
             .filter(dsl::address.eq(&address))
-            .filter(dsl::chain_id.eq(self.chain_id))
             .count()
             .get_result(&mut conn)
             .await?;
@@ -198,32 +638,173 @@ impl Db {
         Ok(res > 0)
     }
 
-    pub async fn history(&self, address: &Address) -> Result<Vec<Txs>> {
+    /// Records that an account's owner just authenticated, on all chains it is registered on
+    /// Used to prioritize their backfill jobs
+    #[instrument(skip(self))]
+    pub async fn touch_auth(&self, address: Address) -> Result<()> {
+        use schema::accounts::dsl;
+
+        let mut conn = self.pool.get().await?;
+
+        let res = update(dsl::accounts)
+            .filter(dsl::address.eq(&address))
+            .set(dsl::last_auth_at.eq(diesel::dsl::now.nullable()))
+            .execute(&mut conn)
+            .await;
+
+        handle_error(res).await
+    }
+
+    /// Records the first time an account's history is requested
+    /// Returns whether this was the first time, in which case the backfill manager is notified,
+    /// since a lazy backfill can now start
+    #[instrument(skip(self))]
+    pub async fn request_history(&self, chain_id: i32, address: &Address) -> Result<bool> {
+        use schema::accounts::dsl;
+
+        let mut conn = self.pool.get().await?;
+
+        let updated = update(dsl::accounts)
+            .filter(dsl::chain_id.eq(chain_id))
+            .filter(dsl::address.eq(address))
+            .filter(dsl::history_requested_at.is_null())
+            .set(dsl::history_requested_at.eq(diesel::dsl::now.nullable()))
+            .execute(&mut conn)
+            .await?;
+
+        if let (true, Some(tx)) = (updated > 0, self.new_job_tx.get(&chain_id)) {
+            tx.send(())?;
+        }
+
+        Ok(updated > 0)
+    }
+
+    /// Scheduling details of all accounts on a chain
+    pub async fn get_account_schedules(&self, chain_id: i32) -> Result<Vec<AccountSchedule>> {
+        use schema::accounts::dsl;
+        let mut conn = self.pool.get().await?;
+
+        let res = dsl::accounts
+            .filter(dsl::chain_id.eq(chain_id))
+            .select(AccountSchedule::as_select())
+            .load(&mut conn)
+            .await?;
+
+        Ok(res)
+    }
+
+    /// Transaction history for an address
+    /// Matches less final than `min_finality` are left out
+    pub async fn history(
+        &self,
+        chain_id: i32,
+        address: &Address,
+        min_finality: Finality,
+    ) -> Result<Vec<TxWithFinality>> {
         use schema::txs::{self, dsl};
         let mut conn = self.pool.get().await?;
 
-        Ok(schema::txs::table
-            .filter(dsl::chain_id.eq(self.chain_id))
+        let chain: Option<Chain> = schema::chains::table
+            .filter(schema::chains::chain_id.eq(chain_id))
+            .select(Chain::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?;
+
+        let max_block = match (min_finality, &chain) {
+            (Finality::Latest, _) => i64::MAX,
+            (Finality::Safe, Some(chain)) => chain.safe_block,
+            (Finality::Finalized, Some(chain)) => chain.finalized_block,
+            // nothing can be confirmed on a chain we haven't synced yet
+            (_, None) => return Ok(vec![]),
+        };
+
+        let matches: Vec<Txs> = schema::txs::table
+            .filter(dsl::chain_id.eq(chain_id))
             .filter(dsl::address.eq(address))
+            .filter(dsl::block_number.le(max_block))
             .select((txs::address, txs::hash))
             .select(Txs::as_select())
             .order(dsl::block_number.asc())
             .load(&mut conn)
-            .await?)
+            .await?;
+
+        Ok(matches
+            .into_iter()
+            .map(|tx| TxWithFinality {
+                finality: chain
+                    .as_ref()
+                    .map_or(Finality::Latest, |c| c.finality_of(tx.block_number)),
+                tx,
+            })
+            .collect())
     }
 
-    pub async fn get_addresses(&self) -> Result<Vec<Address>> {
+    pub async fn get_addresses(&self, chain_id: i32) -> Result<Vec<Address>> {
         use schema::accounts::dsl;
         let mut conn = self.pool.get().await?;
 
         let res = dsl::accounts
-            .filter(dsl::chain_id.eq(self.chain_id))
+            .filter(dsl::chain_id.eq(chain_id))
             .select(dsl::address)
             .load(&mut conn)
             .await?;
         Ok(res)
     }
 
+    /// Stamp of the addresses registered on a chain, computed without loading them
+    pub async fn get_accounts_stamp(&self, chain_id: i32) -> Result<AccountsStamp> {
+        use diesel::{sql_query, sql_types::Integer};
+        let mut conn = self.pool.get().await?;
+
+        let res = sql_query(
+            "SELECT COUNT(*) AS count, MAX(created_at) AS latest FROM accounts WHERE chain_id = $1",
+        )
+        .bind::<Integer, _>(chain_id)
+        .get_result(&mut conn)
+        .await?;
+
+        Ok(res)
+    }
+
+    /// Loads the persisted address filter of a chain, if any
+    pub async fn get_address_filter(&self, chain_id: i32) -> Result<Option<AddressFilter>> {
+        use schema::address_filters::dsl;
+        let mut conn = self.pool.get().await?;
+
+        let res = dsl::address_filters
+            .filter(dsl::chain_id.eq(chain_id))
+            .select(AddressFilter::as_select())
+            .first(&mut conn)
+            .await
+            .optional()?;
+
+        Ok(res)
+    }
+
+    /// Persists a chain's address filter, replacing the previous one
+    #[instrument(skip(self, filter), fields(chain_id = filter.chain_id, bytes = filter.filter.len()))]
+    pub async fn save_address_filter(&self, filter: AddressFilter) -> Result<()> {
+        use diesel::upsert::excluded;
+        use schema::address_filters::dsl;
+        let mut conn = self.pool.get().await?;
+
+        let res = insert_into(dsl::address_filters)
+            .values(&filter)
+            .on_conflict(dsl::chain_id)
+            .do_update()
+            .set((
+                dsl::accounts_count.eq(excluded(dsl::accounts_count)),
+                dsl::accounts_latest.eq(excluded(dsl::accounts_latest)),
+                dsl::filter.eq(excluded(dsl::filter)),
+                dsl::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(&mut conn)
+            .await;
+
+        handle_error(res).await
+    }
+
     #[instrument(skip(self, txs), fields(txs = txs.len()))]
     pub async fn create_txs(&self, txs: Vec<CreateTx>) -> Result<()> {
         use schema::txs::dsl;
@@ -239,14 +820,21 @@ impl Db {
     }
 
     #[instrument(skip(self))]
-    pub async fn create_backfill_job(&self, address: Address, low: i32, high: i32) -> Result<()> {
+    pub async fn create_backfill_job(
+        &self,
+        chain_id: i32,
+        address: Address,
+        low: u64,
+        high: u64,
+    ) -> Result<()> {
         use schema::backfill_jobs::dsl;
+        let (low, high) = (i64::try_from(low)?, i64::try_from(high)?);
         let mut conn = self.pool.get().await?;
 
         let res = insert_into(dsl::backfill_jobs)
             .values((
                 dsl::addresses.eq(vec![address]),
-                dsl::chain_id.eq(self.chain_id),
+                dsl::chain_id.eq(chain_id),
                 dsl::low.eq(low),
                 dsl::high.eq(high),
             ))
@@ -255,19 +843,19 @@ impl Db {
             .await;
 
         // notify backfill job new work is available
-        if let (Ok(_), Some(tx)) = (&res, &self.new_job_tx) {
+        if let (Ok(_), Some(tx)) = (&res, self.new_job_tx.get(&chain_id)) {
             tx.send(())?;
         }
 
         handle_error(res).await
     }
 
-    pub async fn get_backfill_jobs(&self) -> Result<Vec<BackfillJobWithId>> {
+    pub async fn get_backfill_jobs(&self, chain_id: i32) -> Result<Vec<BackfillJobWithId>> {
         use schema::backfill_jobs::dsl;
         let mut conn = self.pool.get().await?;
 
         let res = dsl::backfill_jobs
-            .filter(dsl::chain_id.eq(self.chain_id))
+            .filter(dsl::chain_id.eq(chain_id))
             .select(BackfillJobWithId::as_select())
             .order(dsl::high.desc())
             .load(&mut conn)
@@ -276,31 +864,44 @@ impl Db {
         Ok(res)
     }
 
-    /// Deletes all existing backfill jobs, and rearranges them for optimal I/O
-    /// See `utils::rearrange` for more details
+    /// Deletes all existing backfill jobs for a chain, and rearranges them for optimal I/O
+    /// Large jobs are then split, so that up to `concurrency` workers can walk them in parallel
+    /// See `crate::rearrange` for more details
     #[instrument(skip(self))]
-    pub async fn reorg_backfill_jobs(&self) -> Result<()> {
+    pub async fn reorg_backfill_jobs(
+        &self,
+        chain_id: i32,
+        concurrency: usize,
+        min_job_size: u64,
+        max_job_size: u64,
+    ) -> Result<()> {
+        let min_job_size = i64::try_from(min_job_size)?;
+        let max_job_size = i64::try_from(max_job_size)?;
         use schema::backfill_jobs::dsl;
         let mut conn = self.pool.get().await?;
 
         conn.transaction::<_, diesel::result::Error, _>(|mut conn| {
             async move {
                 let jobs = dsl::backfill_jobs
-                    .filter(dsl::chain_id.eq(self.chain_id))
+                    .filter(dsl::chain_id.eq(chain_id))
                     .select(BackfillJob::as_select())
                     .order(dsl::high.desc())
                     .load(&mut conn)
                     .await?;
 
-                let rearranged = crate::rearrange::rearrange(&jobs);
+                let rearranged = crate::rearrange::rearrange(&jobs, max_job_size);
+                let rearranged = crate::rearrange::split(rearranged, concurrency, min_job_size);
 
-                delete(dsl::backfill_jobs).execute(&mut conn).await?;
+                delete(dsl::backfill_jobs)
+                    .filter(dsl::chain_id.eq(chain_id))
+                    .execute(&mut conn)
+                    .await?;
 
                 let rearranged: Vec<_> = rearranged
                     .into_iter()
                     .map(|j| BackfillJobWithChainId {
                         addresses: j.addresses,
-                        chain_id: self.chain_id,
+                        chain_id,
                         low: j.low,
                         high: j.high,
                     })
@@ -320,14 +921,199 @@ impl Db {
         Ok(())
     }
 
+    /// Splits off the lower part of a backfill job, `[low, at)`, into a new job
+    /// Used when an idle worker steals part of the range another worker has yet to walk
+    /// If the job was already walked down to `at`, it is deleted rather than left with no blocks.
+    /// Returns `None` if there's nothing to split off, e.g. if the job is gone
+    #[instrument(skip(self))]
+    pub async fn split_backfill_job(
+        &self,
+        chain_id: i32,
+        id: i32,
+        at: u64,
+    ) -> Result<Option<BackfillJobWithId>> {
+        use schema::backfill_jobs::dsl;
+        let at = i64::try_from(at)?;
+        let mut conn = self.pool.get().await?;
+
+        let job = conn
+            .transaction::<_, diesel::result::Error, _>(|mut conn| {
+                async move {
+                    let Some(job) = dsl::backfill_jobs
+                        .filter(dsl::id.eq(id))
+                        .select(BackfillJobWithId::as_select())
+                        .for_update()
+                        .get_result(&mut conn)
+                        .await
+                        .optional()?
+                    else {
+                        return Ok(None);
+                    };
+
+                    // the job's worker may have finished its part before getting here
+                    let high = at.min(job.high);
+                    match at >= job.high {
+                        true => {
+                            delete(dsl::backfill_jobs)
+                                .filter(dsl::id.eq(id))
+                                .execute(&mut conn)
+                                .await?
+                        }
+                        false => {
+                            update(dsl::backfill_jobs)
+                                .filter(dsl::id.eq(id))
+                                .set(dsl::low.eq(at))
+                                .execute(&mut conn)
+                                .await?
+                        }
+                    };
+
+                    if job.low >= high {
+                        return Ok(None);
+                    }
+
+                    insert_into(dsl::backfill_jobs)
+                        .values(BackfillJobWithChainId {
+                            addresses: job.addresses,
+                            chain_id,
+                            low: job.low,
+                            high,
+                        })
+                        .returning(BackfillJobWithId::as_returning())
+                        .get_result(&mut conn)
+                        .await
+                        .map(Some)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        Ok(job)
+    }
+
+    /// Records that a range of blocks was searched for a set of addresses
+    /// Backfill walks downwards, so each address's coverage is extended downwards when the new
+    /// range sits right below it, instead of adding a new row
+    #[instrument(skip(self, addresses), fields(addresses = addresses.len()))]
+    pub async fn record_coverage(
+        &self,
+        chain_id: i32,
+        addresses: &[Address],
+        method: BackfillMethod,
+        range: Range<u64>,
+    ) -> Result<()> {
+        use schema::backfill_coverage::dsl;
+        if range.is_empty() {
+            return Ok(());
+        }
+        let (low, high) = (i64::try_from(range.start)?, i64::try_from(range.end)?);
+        let mut conn = self.pool.get().await?;
+
+        conn.transaction::<_, diesel::result::Error, _>(|mut conn| {
+            async move {
+                let extended: Vec<Address> = update(dsl::backfill_coverage)
+                    .filter(dsl::chain_id.eq(chain_id))
+                    .filter(dsl::method.eq(method.as_str()))
+                    .filter(dsl::low.eq(high))
+                    .filter(dsl::address.eq_any(addresses))
+                    .set((dsl::low.eq(low), dsl::updated_at.eq(diesel::dsl::now)))
+                    .returning(dsl::address)
+                    .get_results(&mut conn)
+                    .await?;
+                let extended: HashSet<_> = extended.into_iter().map(|a| a.0).collect();
+
+                let new: Vec<_> = addresses
+                    .iter()
+                    .filter(|a| !extended.contains(&a.0))
+                    .map(|address| Coverage {
+                        chain_id,
+                        address: address.clone(),
+                        method: method.as_str().to_owned(),
+                        low,
+                        high,
+                    })
+                    .collect();
+
+                insert_into(dsl::backfill_coverage)
+                    .values(&new)
+                    .execute(&mut conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Ranges already searched for an address, lowest first
+    #[cfg(test)]
+    pub async fn get_coverage(&self, chain_id: i32, address: &Address) -> Result<Vec<Coverage>> {
+        use schema::backfill_coverage::dsl;
+        let mut conn = self.pool.get().await?;
+
+        let res = dsl::backfill_coverage
+            .filter(dsl::chain_id.eq(chain_id))
+            .filter(dsl::address.eq(address))
+            .select(Coverage::as_select())
+            .order(dsl::low.asc())
+            .load(&mut conn)
+            .await?;
+
+        Ok(res)
+    }
+
+    /// Cuts all backfill jobs of a chain short at `low`, deleting those entirely below it
+    /// Returns how many jobs were affected
+    pub async fn clamp_backfill_jobs(&self, chain_id: i32, low: u64) -> Result<usize> {
+        use schema::backfill_jobs::dsl;
+        let low = i64::try_from(low)?;
+        let mut conn = self.pool.get().await?;
+
+        let res = conn
+            .transaction::<_, diesel::result::Error, _>(|mut conn| {
+                async move {
+                    let deleted = delete(dsl::backfill_jobs)
+                        .filter(dsl::chain_id.eq(chain_id))
+                        .filter(dsl::high.le(low))
+                        .execute(&mut conn)
+                        .await?;
+
+                    let clamped = update(dsl::backfill_jobs)
+                        .filter(dsl::chain_id.eq(chain_id))
+                        .filter(dsl::low.lt(low))
+                        .set(dsl::low.eq(low))
+                        .execute(&mut conn)
+                        .await?;
+
+                    Ok(deleted + clamped)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        Ok(res)
+    }
+
     /// Updates the to_block for a backfill job
+    /// The job is deleted once its whole range has been walked
     pub async fn update_job(&self, id: i32, high: u64) -> Result<()> {
         use schema::backfill_jobs::dsl;
+        let high = i64::try_from(high)?;
         let mut conn = self.pool.get().await?;
 
         let res = update(dsl::backfill_jobs)
             .filter(dsl::id.eq(id))
-            .set(dsl::high.eq(high as i32))
+            .set(dsl::high.eq(high))
+            .execute(&mut conn)
+            .await;
+        handle_error(res).await?;
+
+        let res = delete(dsl::backfill_jobs)
+            .filter(dsl::id.eq(id))
+            .filter(dsl::low.ge(dsl::high))
             .execute(&mut conn)
             .await;
         handle_error(res).await
@@ -348,7 +1134,248 @@ async fn handle_error(res: diesel::QueryResult<usize>) -> Result<()> {
 impl std::fmt::Debug for Db {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Db")
-            .field("chain_id", &self.chain_id)
+            .field("chains", &self.new_accounts_tx.keys())
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use color_eyre::Result;
+    use serial_test::serial;
+
+    use super::*;
+
+    fn create_tx(address: &Address, hash: u8, block_number: i64) -> CreateTx {
+        CreateTx {
+            address: address.clone(),
+            chain_id: 31337,
+            hash: alloy_primitives::B256::repeat_byte(hash).into(),
+            block_number,
+        }
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_rollback_chain() -> Result<()> {
+        let db = Db::connect_test().await?;
+        let config = Config::for_test();
+        let chain_config = &config.chains[0];
+        let chain_id = chain_config.chain_id;
+        db.setup_chain(chain_config).await?;
+
+        let address = Address(alloy_primitives::Address::repeat_byte(0x1));
+        db.register(chain_id, address.clone(), None).await?;
+        db.create_txs(vec![create_tx(&address, 1, 5), create_tx(&address, 2, 10)])
+            .await?;
+        db.create_backfill_job(chain_id, address.clone(), 1, 12)
+            .await?;
+        db.create_backfill_job(chain_id, address.clone(), 9, 12)
+            .await?;
+        let incomplete = [2, 3, 4, 6, 8, 9]
+            .into_iter()
+            .map(|number| IncompleteBlock { chain_id, number })
+            .collect();
+        db.create_incomplete_blocks(incomplete).await?;
+        assert_eq!(
+            db.get_incomplete_ranges(chain_id).await?,
+            vec![(2, 4), (6, 6), (8, 9)]
+        );
+
+        db.rollback_chain(chain_id, 7).await?;
+
+        let history = db.history(chain_id, &address, Finality::Latest).await?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].tx.block_number, 5);
+
+        let jobs = db.get_backfill_jobs(chain_id).await?;
+        assert_eq!(jobs.len(), 1);
+        assert_eq!((jobs[0].low, jobs[0].high), (1, 8));
+
+        assert_eq!(
+            db.get_incomplete_ranges(chain_id).await?,
+            vec![(2, 4), (6, 6)]
+        );
+
+        let chain = db.setup_chain(chain_config).await?;
+        assert_eq!(chain.last_known_block, 7);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_create_blocks_in_chunks() -> Result<()> {
+        let db = Db::connect_test().await?;
+        let config = Config::for_test();
+        let chain_id = config.chains[0].chain_id;
+        db.setup_chain(&config.chains[0]).await?;
+
+        // more rows than fit in a single statement
+        let count = MAX_BIND_PARAMS / 3 + 100;
+        let blocks = (0..count)
+            .map(|i| Block {
+                chain_id,
+                number: i as i64,
+                hash: alloy_primitives::B256::repeat_byte(i as u8).into(),
+            })
+            .collect();
+        db.create_blocks(chain_id, blocks, 0).await?;
+
+        let recent = db.get_recent_blocks(chain_id, count as u64 + 1).await?;
+        assert_eq!(recent.len(), count);
+        assert_eq!(recent[0].number, count as i64 - 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_split_backfill_job() -> Result<()> {
+        let db = Db::connect_test().await?;
+        let config = Config::for_test();
+        let chain_config = &config.chains[0];
+        let chain_id = chain_config.chain_id;
+        db.setup_chain(chain_config).await?;
+
+        let address = Address(alloy_primitives::Address::repeat_byte(0x1));
+        db.create_backfill_job(chain_id, address.clone(), 1, 12)
+            .await?;
+        let job = db.get_backfill_jobs(chain_id).await?.remove(0);
+
+        let stolen = db.split_backfill_job(chain_id, job.id, 6).await?.unwrap();
+        assert_eq!((stolen.low, stolen.high), (1, 6));
+
+        let mut jobs = db.get_backfill_jobs(chain_id).await?;
+        jobs.sort_by_key(|j| j.low);
+        let ranges: Vec<_> = jobs.iter().map(|j| (j.low, j.high)).collect();
+        assert_eq!(ranges, vec![(1, 6), (6, 12)]);
+
+        // a fully walked job is deleted
+        db.update_job(job.id, 6).await?;
+        assert_eq!(db.get_backfill_jobs(chain_id).await?.len(), 1);
+
+        // a job walked down to the split point before it commits leaves no empty job behind
+        db.update_job(stolen.id, 3).await?;
+        let rest = db
+            .split_backfill_job(chain_id, stolen.id, 3)
+            .await?
+            .unwrap();
+        assert_eq!((rest.low, rest.high), (1, 3));
+        let jobs = db.get_backfill_jobs(chain_id).await?;
+        let ranges: Vec<_> = jobs.iter().map(|j| (j.low, j.high)).collect();
+        assert_eq!(ranges, vec![(1, 3)]);
+
+        // nor is there anything to split off a job that is gone
+        assert!(db
+            .split_backfill_job(chain_id, stolen.id, 2)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_raise_history_start() -> Result<()> {
+        let db = Db::connect_test().await?;
+        let config = Config::for_test();
+        let chain_id = config.chains[0].chain_id;
+        db.setup_chain(&config.chains[0]).await?;
+
+        let alice = Address(alloy_primitives::Address::repeat_byte(0xa));
+        let contract = Address(alloy_primitives::Address::repeat_byte(0xc));
+        db.register(chain_id, alice.clone(), None).await?;
+        db.register(chain_id, contract.clone(), None).await?;
+        db.create_backfill_job(chain_id, alice.clone(), 1, 12)
+            .await?;
+        db.create_backfill_job(chain_id, contract.clone(), 1, 12)
+            .await?;
+        db.reorg_backfill_jobs(chain_id, 1, 1, 100).await?;
+        assert_eq!(db.get_backfill_jobs(chain_id).await?.len(), 1);
+
+        // alice turns out not to be a contract
+        assert_eq!(db.get_unchecked_deployments(chain_id).await?.len(), 2);
+        db.raise_history_start(chain_id, &alice, None).await?;
+        let unchecked = db.get_unchecked_deployments(chain_id).await?;
+        assert_eq!(unchecked.len(), 1);
+        assert_eq!(unchecked[0].0 .0, contract.0);
+
+        db.raise_history_start(chain_id, &contract, Some(6)).await?;
+
+        // only alice is searched below the contract's deployment
+        let mut jobs = db.get_backfill_jobs(chain_id).await?;
+        jobs.sort_by_key(|j| j.low);
+        let jobs: Vec<_> = jobs
+            .iter()
+            .map(|j| (j.addresses.len(), j.low, j.high))
+            .collect();
+        assert_eq!(jobs, vec![(1, 1, 6), (2, 6, 12)]);
+
+        assert_eq!(db.get_history_start(chain_id, &contract).await?, Some(6));
+        assert!(db.get_unchecked_deployments(chain_id).await?.is_empty());
+
+        // extending the history below the deployment has it looked up again
+        db.extend_history(chain_id, &contract, 1).await?;
+        let unchecked = db.get_unchecked_deployments(chain_id).await?;
+        assert_eq!(unchecked.len(), 1);
+        assert_eq!(unchecked[0].1, Some(1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_request_history() -> Result<()> {
+        let db = Db::connect_test().await?;
+        let config = Config::for_test();
+        let chain_id = config.chains[0].chain_id;
+        db.setup_chain(&config.chains[0]).await?;
+
+        let address = Address(alloy_primitives::Address::repeat_byte(0x1));
+        db.register(chain_id, address.clone(), None).await?;
+        db.touch_auth(address.clone()).await?;
+
+        assert!(db.request_history(chain_id, &address).await?);
+        assert!(!db.request_history(chain_id, &address).await?);
+
+        let schedules = db.get_account_schedules(chain_id).await?;
+        assert_eq!(schedules.len(), 1);
+        assert!(schedules[0].last_auth_at.is_some());
+        assert!(schedules[0].history_requested_at.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_extend_history() -> Result<()> {
+        let db = Db::connect_test().await?;
+        let config = Config::for_test();
+        let chain_id = config.chains[0].chain_id;
+        db.setup_chain(&config.chains[0]).await?;
+        db.update_chain(chain_id as u64, 200).await?;
+
+        let address = Address(alloy_primitives::Address::repeat_byte(0x1));
+        let other = Address(alloy_primitives::Address::repeat_byte(0x2));
+        db.register(chain_id, address.clone(), Some(100)).await?;
+        assert_eq!(db.get_history_start(chain_id, &address).await?, Some(100));
+
+        // only the missing range is scheduled
+        assert_eq!(db.extend_history(chain_id, &address, 50).await?, Some(50));
+        assert_eq!(db.extend_history(chain_id, &address, 80).await?, Some(50));
+        // so does registering again with an earlier start
+        db.register(chain_id, address.clone(), Some(30)).await?;
+        assert_eq!(db.get_history_start(chain_id, &address).await?, Some(30));
+        // never below the chain's start_block
+        assert_eq!(db.extend_history(chain_id, &address, 0).await?, Some(1));
+        assert_eq!(db.extend_history(chain_id, &other, 0).await?, None);
+
+        let mut jobs = db.get_backfill_jobs(chain_id).await?;
+        jobs.sort_by_key(|j| j.low);
+        let ranges: Vec<_> = jobs.iter().map(|j| (j.low, j.high)).collect();
+        assert_eq!(ranges, vec![(1, 30), (30, 50), (50, 100)]);
+
+        Ok(())
+    }
+}
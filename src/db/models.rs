@@ -2,8 +2,11 @@ use diesel::{pg::Pg, prelude::*};
 use serde::{Deserialize, Serialize};
 
 use super::{
-    schema::{accounts, backfill_jobs, chains, txs},
-    types::{Address, B256},
+    schema::{
+        accounts, address_filters, backfill_coverage, backfill_jobs, blocks, chains,
+        incomplete_blocks, txs,
+    },
+    types::{Address, Finality, B256},
 };
 
 #[derive(Debug, Queryable, Selectable, Serialize)]
@@ -13,6 +16,50 @@ pub struct Account {
     pub chain_id: i32,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    pub last_auth_at: Option<chrono::NaiveDateTime>,
+    pub history_requested_at: Option<chrono::NaiveDateTime>,
+
+    /// Lowest block whose history was requested, if not the chain's `start_block`
+    pub history_start: Option<i64>,
+}
+
+/// What backfill scheduling needs to know about an account
+#[derive(Debug, Queryable, Selectable, Clone)]
+#[diesel(table_name = accounts, check_for_backend(Pg))]
+pub struct AccountSchedule {
+    pub address: Address,
+
+    /// Last time the owner authenticated with the API
+    pub last_auth_at: Option<chrono::NaiveDateTime>,
+
+    /// First time the owner requested their history. Lazy backfills only start after this
+    pub history_requested_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Serialized address matcher of a chain, holding all addresses registered on it, so it needn't
+/// be rebuilt on every boot
+#[derive(Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = address_filters, check_for_backend(Pg))]
+pub struct AddressFilter {
+    pub chain_id: i32,
+
+    /// Stamp of the accounts the filter was built from
+    /// If it doesn't match the current accounts, the filter is stale
+    pub accounts_count: i64,
+    pub accounts_latest: Option<chrono::NaiveDateTime>,
+
+    pub filter: Vec<u8>,
+}
+
+/// Number of accounts registered on a chain, and when the latest one was
+/// Accounts are never removed, so it matches the stamp of a persisted `AddressFilter` only if no
+/// account was registered since
+#[derive(Debug, QueryableByName)]
+pub struct AccountsStamp {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub count: i64,
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Timestamp>)]
+    pub latest: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Debug, Queryable, Selectable, Serialize)]
@@ -21,28 +68,79 @@ pub struct Txs {
     pub address: Address,
     pub chain_id: i32,
     pub hash: B256,
-    pub block_number: i32,
+    pub block_number: i64,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
 }
 
+/// A matched transaction, along with its current confirmation status
+#[derive(Debug, Serialize)]
+pub struct TxWithFinality {
+    #[serde(flatten)]
+    pub tx: Txs,
+    pub finality: Finality,
+}
+
 #[derive(Debug, Deserialize, Insertable)]
 #[diesel(table_name = txs, check_for_backend(Pg))]
 pub struct CreateTx {
     pub address: Address,
     pub chain_id: i32,
     pub hash: B256,
-    pub block_number: i32,
+    pub block_number: i64,
 }
 
 #[derive(Debug, Queryable, Selectable)]
 #[diesel(table_name = chains, check_for_backend(Pg))]
 pub struct Chain {
     pub chain_id: i32,
-    pub start_block: i32,
-    pub last_known_block: i32,
+    pub start_block: i64,
+    pub last_known_block: i64,
     #[allow(dead_code)]
     pub updated_at: chrono::NaiveDateTime,
+    pub safe_block: i64,
+    pub finalized_block: i64,
+}
+
+impl Chain {
+    /// Confirmation status of a block, according to the last known safe and finalized blocks
+    pub fn finality_of(&self, block_number: i64) -> Finality {
+        if block_number <= self.finalized_block {
+            Finality::Finalized
+        } else if block_number <= self.safe_block {
+            Finality::Safe
+        } else {
+            Finality::Latest
+        }
+    }
+}
+
+/// Hash of a block indexed by the forward sync
+/// Only the most recent ones are kept, to detect chain reorganizations
+#[derive(Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = blocks, check_for_backend(Pg))]
+pub struct Block {
+    pub chain_id: i32,
+    pub number: i64,
+    pub hash: B256,
+}
+
+/// A block searched without some of its receipts (e.g.: pruned by the node), so matches by log
+/// topic may be missing from it
+#[derive(Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = incomplete_blocks, check_for_backend(Pg))]
+pub struct IncompleteBlock {
+    pub chain_id: i32,
+    pub number: i64,
+}
+
+/// An inclusive range of block numbers
+#[derive(Debug, QueryableByName)]
+pub struct BlockRange {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub low: i64,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    pub high: i64,
 }
 
 #[derive(Debug, Queryable, Selectable, Insertable, Clone)]
@@ -51,10 +149,10 @@ pub struct BackfillJob {
     pub addresses: Vec<Address>,
 
     /// The low (oldest) block number
-    pub low: i32,
+    pub low: i64,
 
     /// The high (newest) block number
-    pub high: i32,
+    pub high: i64,
 }
 
 #[derive(Debug, Insertable, Clone)]
@@ -65,10 +163,10 @@ pub struct BackfillJobWithChainId {
     pub chain_id: i32,
 
     /// The low (oldest) block number
-    pub low: i32,
+    pub low: i64,
 
     /// The high (newest) block number
-    pub high: i32,
+    pub high: i64,
 }
 
 #[derive(Debug, Queryable, Selectable, Insertable, Clone)]
@@ -78,8 +176,22 @@ pub struct BackfillJobWithId {
     pub addresses: Vec<Address>,
 
     /// The low (oldest) block number
-    pub low: i32,
+    pub low: i64,
 
     /// The high (newest) block number
-    pub high: i32,
+    pub high: i64,
+}
+
+/// A range of blocks already searched for an address, and the method used to search it
+#[derive(Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = backfill_coverage, check_for_backend(Pg))]
+pub struct Coverage {
+    pub chain_id: i32,
+    pub address: Address,
+
+    /// See `crate::config::BackfillMethod`
+    pub method: String,
+
+    pub low: i64,
+    pub high: i64,
 }
@@ -6,6 +6,33 @@ diesel::table! {
         chain_id -> Int4,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        last_auth_at -> Nullable<Timestamp>,
+        history_requested_at -> Nullable<Timestamp>,
+        history_start -> Nullable<Int8>,
+        deployment_checked -> Bool,
+    }
+}
+
+diesel::table! {
+    address_filters (chain_id) {
+        chain_id -> Int4,
+        accounts_count -> Int8,
+        accounts_latest -> Nullable<Timestamp>,
+        filter -> Bytea,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    backfill_coverage (id) {
+        id -> Int4,
+        chain_id -> Int4,
+        address -> Bytea,
+        method -> Text,
+        low -> Int8,
+        high -> Int8,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -14,19 +41,30 @@ diesel::table! {
         id -> Int4,
         addresses -> Array<Bytea>,
         chain_id -> Int4,
-        low -> Int4,
-        high -> Int4,
+        low -> Int8,
+        high -> Int8,
         created_at -> Timestamp,
         updated_at -> Timestamp,
     }
 }
 
+diesel::table! {
+    blocks (chain_id, number) {
+        chain_id -> Int4,
+        number -> Int8,
+        hash -> Bytea,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     chains (chain_id) {
         chain_id -> Int4,
-        start_block -> Int4,
-        last_known_block -> Int4,
+        start_block -> Int8,
+        last_known_block -> Int8,
         updated_at -> Timestamp,
+        safe_block -> Int8,
+        finalized_block -> Int8,
     }
 }
 
@@ -35,12 +73,33 @@ diesel::table! {
         address -> Bytea,
         chain_id -> Int4,
         hash -> Bytea,
-        block_number -> Int4,
+        block_number -> Int8,
         created_at -> Timestamp,
         updated_at -> Timestamp,
     }
 }
 
+diesel::table! {
+    incomplete_blocks (chain_id, number) {
+        chain_id -> Int4,
+        number -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::joinable!(address_filters -> chains (chain_id));
+diesel::joinable!(backfill_coverage -> chains (chain_id));
 diesel::joinable!(backfill_jobs -> chains (chain_id));
+diesel::joinable!(blocks -> chains (chain_id));
+diesel::joinable!(incomplete_blocks -> chains (chain_id));
 
-diesel::allow_tables_to_appear_in_same_query!(accounts, backfill_jobs, chains, txs,);
+diesel::allow_tables_to_appear_in_same_query!(
+    accounts,
+    address_filters,
+    backfill_coverage,
+    backfill_jobs,
+    blocks,
+    chains,
+    incomplete_blocks,
+    txs,
+);
@@ -22,6 +22,16 @@ pub struct U256(pub alloy_primitives::U256);
 #[diesel(sql_type=Bytea)]
 pub struct B256(pub alloy_primitives::B256);
 
+/// Confirmation status of an indexed block, from least to most final
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Finality {
+    #[default]
+    Latest,
+    Safe,
+    Finalized,
+}
+
 impl From<alloy_primitives::Address> for Address {
     fn from(value: alloy_primitives::Address) -> Self {
         Self(value)
@@ -0,0 +1,120 @@
+use std::{collections::HashMap, sync::Arc};
+
+use color_eyre::eyre::{eyre, Result};
+use futures::stream;
+use reth::cli::Cli;
+use reth_exex::{ExExContext, ExExEvent};
+use reth_node_api::FullNodeComponents;
+use reth_node_ethereum::EthereumNode;
+use tokio::sync::mpsc;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::info;
+
+use crate::{
+    api,
+    config::Config,
+    db::Db,
+    sync::{
+        backfill_source, BackfillManager, BlockSource, ChainNotification, Forward, ProviderSource,
+        StopStrategy,
+    },
+};
+
+/// Launches a reth node, with the indexer installed as an Execution Extension
+/// CLI arguments are reth's own. The indexer config is read from `ETHUI_INDEXER_CONFIG`
+pub fn run() -> Result<()> {
+    Cli::parse_args().run(|builder, _| async move {
+        let handle = builder
+            .node(EthereumNode::default())
+            .install_exex("ethui-indexer", |ctx| async move { Ok(indexer(ctx)) })
+            .launch()
+            .await?;
+
+        handle.wait_for_node_exit().await
+    })
+}
+
+/// Forward sync is driven by the node's chain notifications, instead of polling its database
+/// Backfill still reads through the node's provider
+/// Only the chain the node is running is indexed. Other `[[chains]]` entries are ignored
+async fn indexer<Node: FullNodeComponents>(ctx: ExExContext<Node>) -> Result<()> {
+    let config = Config::read_from_env()?;
+    let chain_id = ctx.config.chain.chain.id();
+    let chain_config = config
+        .chains
+        .iter()
+        .find(|c| c.chain_id as u64 == chain_id)
+        .ok_or_else(|| {
+            eyre!(
+                "node is running chain {}, which is not configured",
+                chain_id
+            )
+        })?
+        .clone();
+
+    let (account_tx, account_rx) = mpsc::unbounded_channel();
+    let (job_tx, job_rx) = mpsc::unbounded_channel();
+    let db = Db::connect(
+        &config,
+        HashMap::from([(chain_config.chain_id, account_tx)]),
+        HashMap::from([(chain_config.chain_id, job_tx)]),
+    )
+    .await?;
+    let chain = db.setup_chain(&chain_config).await?;
+
+    let token = CancellationToken::new();
+    let tracker = TaskTracker::new();
+    let source: Arc<dyn BlockSource> = Arc::new(ProviderSource::new(ctx.provider().clone()));
+
+    // new blocks come from the node's notifications, so there is no tip to watch
+    let sync = Forward::new(
+        db.clone(),
+        &config,
+        chain,
+        source.clone(),
+        None,
+        account_rx,
+        token.clone(),
+    )
+    .await?;
+    let backfill = BackfillManager::new(
+        db.clone(),
+        &config,
+        &chain_config,
+        backfill_source(&chain_config, source.clone())?,
+        job_rx,
+        StopStrategy::Token(token.clone()),
+    );
+    let backfills = HashMap::from([(chain_config.chain_id, backfill.concurrency())]);
+    tracker.spawn(backfill.run());
+
+    let sources = HashMap::from([(chain_config.chain_id, source)]);
+    let api = config
+        .clone()
+        .http
+        .map(|_| api::start(db.clone(), config.clone(), sources, backfills));
+    api.map(|t| tracker.spawn(t));
+
+    let ExExContext {
+        notifications,
+        events,
+        ..
+    } = ctx;
+    let notifications = stream::unfold(notifications, |mut rx| async move {
+        rx.recv().await.map(|n| (ChainNotification::from(n), rx))
+    });
+
+    let result = sync
+        .follow(Box::pin(notifications), |height| {
+            // lets reth know it may prune anything up to this block
+            let _ = events.send(ExExEvent::FinishedHeight(height));
+        })
+        .await;
+
+    info!("graceful shutdown initiated...");
+    token.cancel();
+    tracker.close();
+    tracker.wait().await;
+
+    result
+}
@@ -1,10 +1,12 @@
 mod api;
 mod config;
 mod db;
+#[cfg(feature = "exex")]
+mod exex;
 mod rearrange;
 mod sync;
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use color_eyre::eyre::Result;
 use config::Config;
@@ -15,10 +17,20 @@ use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
 
 use self::{
     db::Db,
-    sync::{BackfillManager, Forward, SyncJob},
+    sync::{
+        backfill_source, block_source, BackfillManager, BlockSource, Forward, SyncJob, TipWatcher,
+    },
 };
-use crate::sync::{RethProviderFactory, StopStrategy};
+use crate::sync::StopStrategy;
 
+#[cfg(feature = "exex")]
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    exex::run()
+}
+
+#[cfg(not(feature = "exex"))]
 #[tokio::main]
 async fn main() -> Result<()> {
     setup()?;
@@ -26,39 +38,60 @@ async fn main() -> Result<()> {
     let config = Config::read()?;
 
     // set up a few random things
-    let (account_tx, account_rx) = mpsc::unbounded_channel();
-    let (job_tx, job_rx) = mpsc::unbounded_channel();
-    let db = Db::connect(&config, account_tx, job_tx).await?;
-    let chain = db.setup_chain(&config.chain).await?;
-    let provider_factory = Arc::new(RethProviderFactory::new(&config, &chain)?);
+    let mut accounts_txs = HashMap::new();
+    let mut job_txs = HashMap::new();
+    let mut receivers = Vec::new();
+    for chain in config.chains.iter() {
+        let (account_tx, account_rx) = mpsc::unbounded_channel();
+        let (job_tx, job_rx) = mpsc::unbounded_channel();
+        accounts_txs.insert(chain.chain_id, account_tx);
+        job_txs.insert(chain.chain_id, job_tx);
+        receivers.push((chain, account_rx, job_rx));
+    }
+    let db = Db::connect(&config, accounts_txs, job_txs).await?;
     let token = CancellationToken::new();
+    let tracker = TaskTracker::new();
+    let mut sources: HashMap<i32, Arc<dyn BlockSource>> = HashMap::new();
+    let mut backfills = HashMap::new();
+
+    // setup and spawn each chain's tasks
+    for (chain_config, account_rx, job_rx) in receivers {
+        let chain = db.setup_chain(chain_config).await?;
+        let source = block_source(chain_config).await?;
+
+        let tips = TipWatcher::new(chain_config, &config.sync, source.clone(), token.clone());
+        let sync = Forward::new(
+            db.clone(),
+            &config,
+            chain,
+            source.clone(),
+            Some(tips.subscribe()),
+            account_rx,
+            token.clone(),
+        )
+        .await?;
+        // also used by the API, which reports blocks missing from all of them
+        let source = backfill_source(chain_config, source)?;
+        let backfill = BackfillManager::new(
+            db.clone(),
+            &config,
+            chain_config,
+            source.clone(),
+            job_rx,
+            StopStrategy::Token(token.clone()),
+        );
+
+        backfills.insert(chain_config.chain_id, backfill.concurrency());
+        tracker.spawn(tips.run());
+        tracker.spawn(sync.run());
+        tracker.spawn(backfill.run());
+        sources.insert(chain_config.chain_id, source);
+    }
 
-    // setup each task
-    let sync = Forward::new(
-        db.clone(),
-        &config,
-        chain,
-        provider_factory.clone(),
-        account_rx,
-        token.clone(),
-    )
-    .await?;
-    let backfill = BackfillManager::new(
-        db.clone(),
-        &config,
-        provider_factory.clone(),
-        job_rx,
-        StopStrategy::Token(token.clone()),
-    );
     let api = config
         .clone()
         .http
-        .map(|_| api::start(db.clone(), config, provider_factory.clone()));
-
-    // spawn and track tasks
-    let tracker = TaskTracker::new();
-    tracker.spawn(sync.run());
-    tracker.spawn(backfill.run());
+        .map(|_| api::start(db.clone(), config.clone(), sources, backfills));
     api.map(|t| tracker.spawn(t));
 
     // termination handling
@@ -73,6 +106,7 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+#[cfg(not(feature = "exex"))]
 fn setup() -> Result<()> {
     color_eyre::install()?;
 
@@ -1,68 +1,121 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::BTreeMap;
 
 use crate::db::models::BackfillJob;
 
-/// Assumes jobs are already sorted by from_block
-pub fn rearrange(jobs: &[BackfillJob]) -> Vec<BackfillJob> {
-    let points = jobs
+/// Rearranges jobs into non-overlapping ones, where each block range is walked by a single job,
+/// for the exact set of addresses that need it
+///
+/// Sweeps over the jobs' boundaries in order, keeping count of which addresses are active in
+/// between. Adjacent segments with the same address set are coalesced, and then cut into jobs of
+/// at most `max_size` blocks
+/// Jobs are returned sorted by block range, with their addresses sorted as well
+pub fn rearrange(jobs: &[BackfillJob], max_size: i64) -> Vec<BackfillJob> {
+    // each job adds its addresses at `low`, and removes them at `high`
+    let mut events: Vec<_> = jobs
         .iter()
-        .filter(|j| j.low != j.high) // filter out empty jobs
-        .fold(BTreeSet::new(), |mut acc, j| {
-            acc.insert(j.low);
-            acc.insert(j.high);
-            acc
-        });
+        .filter(|j| j.low < j.high)
+        .flat_map(|j| [(j.low, true, j), (j.high, false, j)])
+        .collect();
+    events.sort_unstable_by_key(|(point, ..)| *point);
 
-    let sorted_points: Vec<i32> = points.into_iter().collect();
+    let mut active: BTreeMap<alloy_primitives::Address, usize> = BTreeMap::new();
+    let mut set = Vec::new();
+    let mut segments: Vec<(i64, i64, Vec<alloy_primitives::Address>)> = Vec::new();
 
-    let mut range_map = HashMap::new();
-    let mut size = 0;
+    let mut i = 0;
+    while i < events.len() {
+        let point = events[i].0;
 
-    for i in 0..sorted_points.len().saturating_sub(1) {
-        let start = sorted_points[i];
-        let end = sorted_points[i + 1];
-        let range = start..end;
-
-        let mut addresses = Vec::new();
-        for job in jobs.iter() {
-            if job.low >= end {
-                continue;
-            };
+        // apply all events at this point, noting whether the set of active addresses changed
+        let mut changed = false;
+        while let Some((_, add, job)) = events.get(i).filter(|(p, ..)| *p == point) {
+            for address in job.addresses.iter() {
+                changed |= match *add {
+                    true => {
+                        let count = active.entry(address.0).or_default();
+                        *count += 1;
+                        *count == 1
+                    }
+                    false => {
+                        let count = active.get_mut(&address.0).expect("address not active");
+                        *count -= 1;
+                        *count == 0 && active.remove(&address.0).is_some()
+                    }
+                };
+            }
+            i += 1;
+        }
 
-            let job_range = job.low..job.high;
+        let Some(&(next, ..)) = events.get(i) else {
+            break;
+        };
 
-            if job_range.contains(&range.start) && job_range.contains(&(range.end - 1)) {
-                addresses.extend_from_slice(&job.addresses)
-            }
+        if changed {
+            set = active.keys().copied().collect();
+        }
+        if set.is_empty() {
+            continue;
         }
 
-        size += addresses.len();
-        if !addresses.is_empty() {
-            range_map.insert((start, end), addresses);
+        match segments.last_mut() {
+            Some((_, high, last)) if *high == point && *last == set => *high = next,
+            _ => segments.push((point, next, set.clone())),
         }
     }
 
-    let mut res = Vec::with_capacity(size);
-    range_map.into_iter().for_each(|((low, high), addresses)| {
-        res.push(BackfillJob {
-            addresses,
-            low,
-            high,
+    let max_size = max_size.max(1);
+    segments
+        .into_iter()
+        .flat_map(|(low, high, set)| {
+            let addresses: Vec<_> = set.into_iter().map(Into::into).collect();
+            (low..high)
+                .step_by(max_size as usize)
+                .map(move |start| BackfillJob {
+                    addresses: addresses.clone(),
+                    low: start,
+                    high: start.saturating_add(max_size).min(high),
+                })
         })
-    });
+        .collect()
+}
+
+/// Splits jobs into sub-ranges, so that there's roughly one job per available worker
+/// Each job gets a share of the workers proportional to its size, but is never split into
+/// sub-ranges smaller than `min_size` blocks. Jobs are left as is if there are enough of them
+pub fn split(jobs: Vec<BackfillJob>, concurrency: usize, min_size: i64) -> Vec<BackfillJob> {
+    if jobs.len() >= concurrency {
+        return jobs;
+    }
 
-    res
+    let total: i64 = jobs.iter().map(|j| j.high - j.low).sum();
+    let min_size = min_size.max(1);
+
+    jobs.into_iter()
+        .flat_map(|job| {
+            let size = job.high - job.low;
+            let parts = (size * concurrency as i64 / total.max(1))
+                .min(size / min_size)
+                .max(1);
+
+            (0..parts).map(move |i| BackfillJob {
+                addresses: job.addresses.clone(),
+                low: job.low + size * i / parts,
+                high: job.low + size * (i + 1) / parts,
+            })
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use alloy_primitives::Address;
+    use proptest::prelude::*;
     use rstest::*;
 
     use super::*;
 
-    #[derive(Debug, PartialEq)]
-    struct FakeJob(Vec<u8>, i32, i32);
+    #[derive(Debug, PartialEq, Clone)]
+    struct FakeJob(Vec<u8>, i64, i64);
 
     #[derive(Debug)]
     struct Fixture {
@@ -74,7 +127,31 @@ mod tests {
     fn adjacent_jobs_1() -> Fixture {
         Fixture {
             input: vec![FakeJob(vec![0x1], 0, 10), FakeJob(vec![0x1], 10, 20)],
-            output: vec![FakeJob(vec![0x1], 0, 10), FakeJob(vec![0x1], 10, 20)],
+            output: vec![FakeJob(vec![0x1], 0, 20)],
+        }
+    }
+
+    #[fixture]
+    fn adjacent_jobs_2() -> Fixture {
+        Fixture {
+            input: vec![
+                FakeJob(vec![0x1], 0, 10),
+                FakeJob(vec![0x2], 10, 20),
+                FakeJob(vec![0x1], 20, 30),
+            ],
+            output: vec![
+                FakeJob(vec![0x1], 0, 10),
+                FakeJob(vec![0x2], 10, 20),
+                FakeJob(vec![0x1], 20, 30),
+            ],
+        }
+    }
+
+    #[fixture]
+    fn overlapping_same_address() -> Fixture {
+        Fixture {
+            input: vec![FakeJob(vec![0x1], 1, 12), FakeJob(vec![0x1], 9, 12)],
+            output: vec![FakeJob(vec![0x1], 1, 12)],
         }
     }
 
@@ -160,6 +237,8 @@ mod tests {
 
     #[rstest]
     #[case(adjacent_jobs_1())]
+    #[case(adjacent_jobs_2())]
+    #[case(overlapping_same_address())]
     #[case(same_range_different_addresses())]
     #[case(empty_range())]
     #[case(single_block())]
@@ -169,22 +248,113 @@ mod tests {
     #[case(mix4())]
     fn test(#[case] fixture: Fixture) {
         let jobs = to_jobs(fixture.input);
-        let mut result = rearrange(&jobs);
-        result.sort_by(|j, j2| j.low.cmp(&j2.low));
+        let result = rearrange(&jobs, i64::MAX);
 
-        assert_eq!(result.len(), fixture.output.len());
+        assert_eq!(to_fakes(result), fixture.output);
+    }
 
-        for (job, expectation) in result.into_iter().zip(fixture.output.iter()) {
-            let fake = FakeJob(
-                job.addresses
-                    .into_iter()
-                    .map(|a| a.0.as_slice()[0])
-                    .collect(),
-                job.low,
-                job.high,
-            );
-            assert_eq!(&fake, expectation);
+    #[test]
+    fn test_max_size() {
+        let jobs = to_jobs(vec![FakeJob(vec![0x1], 0, 25), FakeJob(vec![0x2], 20, 25)]);
+        let result = rearrange(&jobs, 10);
+
+        assert_eq!(
+            to_fakes(result),
+            vec![
+                FakeJob(vec![0x1], 0, 10),
+                FakeJob(vec![0x1], 10, 20),
+                FakeJob(vec![0x1, 0x2], 20, 25),
+            ]
+        );
+    }
+
+    fn job_strategy() -> impl Strategy<Value = FakeJob> {
+        (prop::collection::vec(1u8..5, 1..3), 0i64..100, 0i64..40)
+            .prop_map(|(ids, low, len)| FakeJob(ids, low, low + len))
+    }
+
+    /// Every (address, block) pair covered by the input
+    fn pairs(fakes: &[FakeJob]) -> BTreeMap<(u8, i64), usize> {
+        let mut pairs = BTreeMap::new();
+        for FakeJob(ids, low, high) in fakes {
+            for id in ids {
+                for block in *low..*high {
+                    *pairs.entry((*id, block)).or_default() += 1;
+                }
+            }
+        }
+        pairs
+    }
+
+    proptest! {
+        #[test]
+        fn prop_covers_each_pair_once(
+            input in prop::collection::vec(job_strategy(), 0..8),
+            max_size in 1i64..30,
+        ) {
+            let output = to_fakes(rearrange(&to_jobs(input.clone()), max_size));
+
+            let expected: BTreeMap<_, _> = pairs(&input).into_keys().map(|k| (k, 1)).collect();
+            prop_assert_eq!(pairs(&output), expected);
+        }
+
+        #[test]
+        fn prop_sorted_and_bounded(
+            input in prop::collection::vec(job_strategy(), 0..8),
+            max_size in 1i64..30,
+        ) {
+            let output = to_fakes(rearrange(&to_jobs(input), max_size));
+
+            for FakeJob(_, low, high) in output.iter() {
+                prop_assert!(low < high);
+                prop_assert!(high - low <= max_size);
+            }
+            for pair in output.windows(2) {
+                prop_assert!(pair[0].2 <= pair[1].1);
+            }
         }
+
+        #[test]
+        fn prop_coalesced(input in prop::collection::vec(job_strategy(), 0..8)) {
+            let output = to_fakes(rearrange(&to_jobs(input), i64::MAX));
+
+            for pair in output.windows(2) {
+                prop_assert!(pair[0].2 != pair[1].1 || pair[0].0 != pair[1].0);
+            }
+        }
+    }
+
+    fn to_fakes(jobs: Vec<BackfillJob>) -> Vec<FakeJob> {
+        jobs.into_iter()
+            .map(|job| {
+                FakeJob(
+                    job.addresses
+                        .into_iter()
+                        .map(|a| a.0.as_slice()[0])
+                        .collect(),
+                    job.low,
+                    job.high,
+                )
+            })
+            .collect()
+    }
+
+    #[rstest]
+    #[case(vec![FakeJob(vec![0x1], 0, 100)], 4, 10, vec![(0, 25), (25, 50), (50, 75), (75, 100)])]
+    #[case(vec![FakeJob(vec![0x1], 0, 100)], 4, 40, vec![(0, 50), (50, 100)])]
+    #[case(vec![FakeJob(vec![0x1], 0, 10)], 4, 1, vec![(0, 2), (2, 5), (5, 7), (7, 10)])]
+    #[case(vec![FakeJob(vec![0x1], 0, 90), FakeJob(vec![0x2], 90, 100)], 4, 1, vec![(0, 30), (30, 60), (60, 90), (90, 100)])]
+    #[case(vec![FakeJob(vec![0x1], 0, 10), FakeJob(vec![0x2], 10, 20)], 2, 1, vec![(0, 10), (10, 20)])]
+    fn test_split(
+        #[case] input: Vec<FakeJob>,
+        #[case] concurrency: usize,
+        #[case] min_size: i64,
+        #[case] expected: Vec<(i64, i64)>,
+    ) {
+        let result = split(to_jobs(input), concurrency, min_size);
+        let ranges: Vec<_> = result.iter().map(|j| (j.low, j.high)).collect();
+
+        assert_eq!(ranges, expected);
     }
 
     fn to_jobs(ranges: Vec<FakeJob>) -> Vec<BackfillJob> {
@@ -1,20 +1,33 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use async_trait::async_trait;
-use color_eyre::eyre::Result;
-use reth_provider::HeaderProvider;
+use color_eyre::eyre::{Report, Result};
 use tokio::{
     select,
     sync::{mpsc::UnboundedReceiver, RwLock, Semaphore},
-    time::sleep,
+    time::{interval, sleep, MissedTickBehavior},
 };
 use tokio_util::sync::CancellationToken;
-use tracing::{info, instrument};
+use tracing::{info, instrument, trace, warn};
 
-use super::{RethProviderFactory, SyncJob, Worker};
+use super::{
+    matcher::Matcher,
+    source::{deployment_block, Pruned},
+    AdaptiveConcurrency, BlockSource, SyncJob, Worker,
+};
 use crate::{
-    config::Config,
-    db::{models::BackfillJobWithId, Db},
+    config::{BackfillMethod, BackfillPolicy, ChainConfig, Config, PipelineConfig},
+    db::{
+        models::{AccountSchedule, BackfillJobWithId},
+        types::Address,
+        Db,
+    },
 };
 
 #[derive(Debug)]
@@ -30,7 +43,7 @@ pub enum StopStrategy {
 
 impl StopStrategy {
     fn is_on_finish(&self) -> bool {
-        matches!(self, StopStrategy::Token(_))
+        matches!(self, StopStrategy::OnFinish)
     }
 }
 
@@ -40,61 +53,219 @@ impl StopStrategy {
 /// `crate::db::rearrange_backfill`
 pub struct BackfillManager {
     db: Db,
-    concurrency: usize,
+    chain: ChainConfig,
+    concurrency: Arc<AdaptiveConcurrency>,
     jobs_rcv: UnboundedReceiver<()>,
     config: Arc<RwLock<Config>>,
     stop: StopStrategy,
-    provider_factory: Arc<RethProviderFactory>,
+    source: Arc<dyn BlockSource>,
 }
 
 impl BackfillManager {
     pub fn new(
         db: Db,
         config: &Config,
-        provider_factory: Arc<RethProviderFactory>,
+        chain: &ChainConfig,
+        source: Arc<dyn BlockSource>,
         jobs_rcv: UnboundedReceiver<()>,
         stop: StopStrategy,
     ) -> Self {
         Self {
             db,
+            chain: chain.clone(),
             jobs_rcv,
-            provider_factory,
+            source,
             config: Arc::new(RwLock::new(config.clone())),
-            concurrency: config.sync.backfill_concurrency,
+            concurrency: Arc::new(AdaptiveConcurrency::new(&config.sync)),
             stop,
         }
     }
 
-    #[instrument(name = "backfill", skip(self), fields(concurrency = self.concurrency))]
+    /// Handle to the concurrency controller, for inspecting its current value and bounds
+    pub fn concurrency(&self) -> Arc<AdaptiveConcurrency> {
+        self.concurrency.clone()
+    }
+
+    #[instrument(name = "backfill", skip(self), fields(chain_id = self.chain.chain_id))]
     pub async fn run(mut self) -> Result<()> {
+        let pruned = self.source.pruned().await?;
+        if pruned != Pruned::default() {
+            warn!(
+                event = "pruned",
+                receipts = ?pruned.receipts,
+                senders = ?pruned.senders,
+                account_history = ?pruned.account_history
+            );
+        }
+
+        // shared across rounds, so the controller can resize it while workers are running
+        let permits = Arc::new(Permits::new(self.concurrency.current()));
+        let interval_secs = self
+            .config
+            .read()
+            .await
+            .sync
+            .adaptive_concurrency
+            .interval_secs;
+        let controller = tokio::spawn(Self::control(
+            self.concurrency.clone(),
+            permits.clone(),
+            Duration::from_secs(interval_secs.max(1)),
+        ));
+
+        let res = self.run_rounds(permits).await;
+        controller.abort();
+        res
+    }
+
+    /// Periodically samples throughput, and grows or shrinks the worker permits accordingly
+    async fn control(
+        concurrency: Arc<AdaptiveConcurrency>,
+        permits: Arc<Permits>,
+        period: Duration,
+    ) {
+        let mut ticker = interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last = ticker.tick().await;
+
+        loop {
+            let now = ticker.tick().await;
+            let prev = concurrency.current();
+            let next = concurrency.adjust(now - last);
+            last = now;
+            if next == prev {
+                continue;
+            }
+
+            let status = concurrency.status();
+            info!(
+                event = "concurrency",
+                from = prev,
+                to = next,
+                blocks_per_sec = status.blocks_per_sec,
+                flush_latency_ms = status.flush_latency_ms
+            );
+
+            permits.resize(prev, next);
+        }
+    }
+
+    /// Moves the history start of newly registered contracts up to their deployment, since they
+    /// can have no activity before it
+    /// This is done here rather than as accounts register, so that the forward sync isn't held up
+    /// by it. Each account is only looked up once: if its deployment can't be found (e.g.: over
+    /// JSON-RPC without an archive node), its history is kept from its previous start
+    async fn resolve_deployments(&self) -> Result<()> {
+        let chain_start = u64::try_from(self.chain.start_block)?;
+        for (address, start) in self
+            .db
+            .get_unchecked_deployments(self.chain.chain_id)
+            .await?
+        {
+            let range = start.unwrap_or(chain_start).max(chain_start)..self.source.tip().await? + 1;
+            let deployed = match deployment_block(&*self.source, address.0, range).await {
+                Ok(deployed) => deployed,
+                Err(err) => {
+                    warn!(event = "deployment_block", address = %address.0, error = %err);
+                    None
+                }
+            };
+
+            self.db
+                .raise_history_start(self.chain.chain_id, &address, deployed)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn run_rounds(&mut self, permits: Arc<Permits>) -> Result<()> {
         loop {
-            let semaphore = Arc::new(Semaphore::new(self.concurrency));
             let inner_cancel = CancellationToken::new();
 
-            self.db.reorg_backfill_jobs().await?;
-            let jobs = self.db.get_backfill_jobs().await?;
+            let (min_job_size, max_job_size, policy) = {
+                let config = self.config.read().await;
+                (
+                    config.sync.min_job_size,
+                    config.sync.max_job_size,
+                    config.sync.backfill_policy,
+                )
+            };
+            self.resolve_deployments().await?;
+
+            // blocks without receipts can't be fully searched, so jobs stop short of them
+            let complete_from = self.source.pruned().await?.complete_from();
+            if self
+                .db
+                .clamp_backfill_jobs(self.chain.chain_id, complete_from)
+                .await?
+                > 0
+            {
+                warn!(event = "pruned", complete_from);
+            }
+
+            self.db
+                .reorg_backfill_jobs(
+                    self.chain.chain_id,
+                    self.concurrency.current(),
+                    min_job_size,
+                    max_job_size,
+                )
+                .await?;
+            let jobs = self.db.get_backfill_jobs(self.chain.chain_id).await?;
+            let accounts = self.db.get_account_schedules(self.chain.chain_id).await?;
+            let jobs = schedule(jobs, &accounts, policy);
 
             if self.stop.is_on_finish() && jobs.is_empty() {
                 break;
             }
 
-            let workers = jobs
-                .into_iter()
-                .map(|job| {
+            // workers take the highest priority job left as soon as they get a permit
+            let running = Arc::new(RunningJobs::default());
+            let queue = Arc::new(Mutex::new(VecDeque::from(jobs)));
+            let workers = (0..queue.lock().unwrap().len())
+                .map(|_| {
                     let db = self.db.clone();
-                    let factory = self.provider_factory.clone();
-                    let semaphore = semaphore.clone();
+                    let chain = self.chain.clone();
+                    let source = self.source.clone();
+                    let permits = permits.clone();
                     let config = self.config.clone();
                     let token = inner_cancel.clone();
+                    let running = running.clone();
+                    let queue = queue.clone();
+                    let concurrency = self.concurrency.clone();
                     tokio::spawn(async move {
-                        let _permit = semaphore.acquire().await.unwrap();
-                        if token.is_cancelled() {
-                            return Ok(());
-                        }
-                        let worker = Backfill::new_worker(db, config, job, factory, token)
+                        let _permit = permits.acquire().await?;
+                        let job = queue.lock().unwrap().pop_front();
+
+                        let mut next = job;
+                        while let Some(job) = next.take() {
+                            if token.is_cancelled() {
+                                return Ok(());
+                            }
+
+                            let range = running.start(&job)?;
+                            let worker = Backfill::new_worker(
+                                db.clone(),
+                                config.clone(),
+                                &chain,
+                                &job,
+                                range,
+                                source.clone(),
+                                concurrency.clone(),
+                                token.clone(),
+                            )
                             .await
                             .unwrap();
-                        worker.run().await
+                            worker.run().await?;
+                            running.finish(job.id);
+
+                            // once no jobs are left waiting for a permit, help with running ones
+                            if queue.lock().unwrap().is_empty() {
+                                next = running.steal(&db, chain.chain_id, min_job_size).await?;
+                            }
+                        }
+
+                        Ok::<_, Report>(())
                     })
                 })
                 .collect::<Vec<_>>();
@@ -137,82 +308,420 @@ impl BackfillManager {
     }
 }
 
+/// Slots for running workers, resized at runtime as the concurrency is adjusted
+/// Permits held by running workers can't be taken back, so the part of a shrink that can't be
+/// applied right away is owed, and settled as they are released
+#[derive(Debug)]
+struct Permits {
+    semaphore: Semaphore,
+
+    /// Permits to drop as they are released, instead of making them available again
+    owed: Mutex<usize>,
+}
+
+/// A worker's slot, given back to `Permits` when dropped
+#[derive(Debug)]
+struct Permit(Arc<Permits>);
+
+impl Permits {
+    fn new(permits: usize) -> Self {
+        Self {
+            semaphore: Semaphore::new(permits),
+            owed: Mutex::new(0),
+        }
+    }
+
+    /// Waits for a free slot
+    async fn acquire(self: &Arc<Self>) -> Result<Permit> {
+        // released by hand on drop, so that owed permits can be settled first
+        self.semaphore.acquire().await?.forget();
+        Ok(Permit(self.clone()))
+    }
+
+    fn release(&self) {
+        let mut owed = self.owed.lock().unwrap();
+        match *owed {
+            0 => self.semaphore.add_permits(1),
+            _ => *owed -= 1,
+        }
+    }
+
+    /// Goes from `from` to `to` slots
+    /// Growing first cancels whatever is still owed from previous shrinks
+    fn resize(&self, from: usize, to: usize) {
+        let mut owed = self.owed.lock().unwrap();
+        if to > from {
+            let cancelled = (to - from).min(*owed);
+            *owed -= cancelled;
+            self.semaphore.add_permits(to - from - cancelled);
+        } else {
+            let forgotten = self.semaphore.forget_permits(from - to);
+            *owed += from - to - forgotten;
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
 #[derive(Debug)]
 pub struct Backfill {
     job_id: i32,
-    high: u64,
-    low: u64,
+
+    /// What's left of the job's range. Shared, so that other workers can steal part of it
+    range: Arc<JobRange>,
+
+    /// How many blocks to read at once
+    batch_size: u64,
+
+    /// Parallelism of each stage
+    pipeline: PipelineConfig,
+
+    /// How to find the blocks to search
+    method: BackfillMethod,
+
+    /// Where throughput is reported, to tune the number of workers
+    concurrency: Arc<AdaptiveConcurrency>,
 }
 
-#[async_trait]
-impl SyncJob for Worker<Backfill> {
-    #[instrument(skip(self), fields(chain_id = self.chain.chain_id))]
-    async fn run(mut self) -> Result<()> {
-        for block in (self.inner.low..self.inner.high).rev() {
-            let provider = self.provider_factory.get()?;
-            // start by checking shutdown signal
-            if self.cancellation_token.is_cancelled() {
-                // the final flush after the loop would skip all the blocks we canceled
-                // so we flush with the current block instead
-                self.flush(block).await?;
-                return Ok(());
-            }
+/// The part of a job's range not yet claimed by its worker
+/// The worker claims batches from the top, while thieves take the lower half of what's left
+#[derive(Debug)]
+pub struct JobRange {
+    inner: Mutex<Range<u64>>,
+}
 
-            let header = provider.header_by_number(block)?.unwrap();
-            self.process_block(&header).await?;
-            self.maybe_flush(block).await?;
+impl JobRange {
+    fn new(range: Range<u64>) -> Self {
+        Self {
+            inner: Mutex::new(range),
+        }
+    }
 
-            if block % 10 == 0 {
-                tokio::task::yield_now().await;
-            }
+    /// Claims the next batch of up to `size` blocks, from the top of the range
+    fn claim(&self, size: u64) -> Option<Range<u64>> {
+        let mut range = self.inner.lock().unwrap();
+        if range.is_empty() {
+            return None;
         }
 
-        self.flush(self.inner.low).await?;
+        let low = range.end.saturating_sub(size).max(range.start);
+        let claimed = low..range.end;
+        range.end = low;
+        Some(claimed)
+    }
 
-        info!("closing backfill worker");
-        Ok(())
+    /// Takes the lower half of the range, if each half is at least `min_size` blocks
+    fn steal(&self, min_size: u64) -> Option<Range<u64>> {
+        let mut range = self.inner.lock().unwrap();
+        let remaining = range.end.saturating_sub(range.start);
+        if remaining < min_size.max(1) * 2 {
+            return None;
+        }
+
+        let mid = range.start + remaining / 2;
+        let stolen = range.start..mid;
+        range.start = mid;
+        Some(stolen)
+    }
+
+    fn len(&self) -> u64 {
+        let range = self.inner.lock().unwrap();
+        range.end.saturating_sub(range.start)
+    }
+
+    /// Top of what's left to claim
+    fn high(&self) -> u64 {
+        self.inner.lock().unwrap().end
     }
 }
 
-impl Worker<Backfill> {
-    /// if the buffer is sufficiently large, flush it to the database
-    /// and update chain tip
-    pub async fn maybe_flush(&mut self, last_block: u64) -> Result<()> {
-        if self.buffer.len() >= self.buffer_capacity {
-            self.flush(last_block).await?;
-        }
+/// Orders backfill jobs by priority, leaving out those that shouldn't start yet
+/// Jobs for recently authenticated accounts come first, and newer ranges before older ones, so
+/// that each account's recent history is filled first
+/// Under the lazy policy, only jobs with at least one account whose history was requested are
+/// kept. Other accounts sharing the same job are walked along with it, at no extra cost
+fn schedule(
+    jobs: Vec<BackfillJobWithId>,
+    accounts: &[AccountSchedule],
+    policy: BackfillPolicy,
+) -> Vec<BackfillJobWithId> {
+    let accounts: HashMap<_, _> = accounts.iter().map(|a| (a.address.0, a)).collect();
+    let accounts_of = |job: &BackfillJobWithId| {
+        job.addresses
+            .iter()
+            .filter_map(|address| accounts.get(&address.0))
+            .collect::<Vec<_>>()
+    };
 
-        Ok(())
+    let mut jobs: Vec<_> = jobs
+        .into_iter()
+        .filter(|job| match policy {
+            BackfillPolicy::Eager => true,
+            BackfillPolicy::Lazy => accounts_of(job)
+                .iter()
+                .any(|a| a.history_requested_at.is_some()),
+        })
+        .map(|job| {
+            let last_auth = accounts_of(&job)
+                .iter()
+                .filter_map(|a| a.last_auth_at)
+                .max();
+            (last_auth, job)
+        })
+        .collect();
+
+    jobs.sort_by_key(|(last_auth, job)| (Reverse(*last_auth), Reverse(job.high)));
+    jobs.into_iter().map(|(_, job)| job).collect()
+}
+
+/// Jobs currently being walked by a worker, which idle workers may steal from
+#[derive(Debug, Default)]
+struct RunningJobs {
+    jobs: Mutex<HashMap<i32, Arc<JobRange>>>,
+}
+
+impl RunningJobs {
+    fn start(&self, job: &BackfillJobWithId) -> Result<Arc<JobRange>> {
+        let range = Arc::new(JobRange::new(
+            u64::try_from(job.low)?..u64::try_from(job.high)?,
+        ));
+        self.jobs.lock().unwrap().insert(job.id, range.clone());
+        Ok(range)
     }
 
-    // empties the buffer and updates chain tip
-    pub async fn flush(&mut self, last_block: u64) -> Result<()> {
-        let txs = self.drain_buffer();
+    fn finish(&self, id: i32) {
+        self.jobs.lock().unwrap().remove(&id);
+    }
 
-        self.db.create_txs(txs).await?;
-        self.db.update_job(self.inner.job_id, last_block).await?;
+    /// Steals the lower half of the largest running job, and persists it as a new job
+    async fn steal(
+        &self,
+        db: &Db,
+        chain_id: i32,
+        min_size: u64,
+    ) -> Result<Option<BackfillJobWithId>> {
+        let stolen = {
+            let jobs = self.jobs.lock().unwrap();
+            jobs.iter()
+                .max_by_key(|(_, range)| range.len())
+                .and_then(|(id, range)| Some((*id, range.steal(min_size)?)))
+        };
 
+        match stolen {
+            Some((id, stolen)) => {
+                trace!(
+                    event = "steal",
+                    from = id,
+                    low = stolen.start,
+                    high = stolen.end
+                );
+                db.split_backfill_job(chain_id, id, stolen.end).await
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl SyncJob for Worker<Backfill> {
+    #[instrument(skip(self), fields(chain_id = self.chain.chain_id))]
+    async fn run(mut self) -> Result<()> {
+        // blocks are read in ascending batches, but batches are claimed from the top of the range
+        let batch_size = self.inner.batch_size.max(1);
+        let range = self.inner.range.clone();
+        let ranges = std::iter::from_fn(move || range.claim(batch_size));
+
+        let db = self.db.clone();
+        let job_id = self.inner.job_id;
+        let chain_id = self.chain.chain_id;
+        let pipeline = self.inner.pipeline.clone();
+        let concurrency = self.inner.concurrency.clone();
+        let addresses: Arc<Vec<Address>> = Arc::new(
+            self.matcher
+                .read()
+                .unwrap()
+                .addresses()
+                .map(Into::into)
+                .collect(),
+        );
+
+        // checkpoints are contiguous, from the top of the range down
+        let mut covered_to = self.inner.range.high();
+        let checkpoint = move |range: Range<u64>, method| {
+            let covered = range.start..covered_to;
+            covered_to = range.start;
+            let db = db.clone();
+            let addresses = addresses.clone();
+            async move {
+                db.update_job(job_id, range.start).await?;
+                db.record_coverage(chain_id, &addresses, method, covered)
+                    .await
+            }
+        };
+
+        let method = self.inner.method;
+        self.pipeline(ranges, &pipeline, method, &concurrency, checkpoint)
+            .await?;
+
+        info!("closing backfill worker");
         Ok(())
     }
 }
 
 impl Backfill {
+    #[allow(clippy::too_many_arguments)]
     async fn new_worker(
         db: Db,
         config: Arc<RwLock<Config>>,
-        job: BackfillJobWithId,
-        provider_factory: Arc<RethProviderFactory>,
+        chain: &ChainConfig,
+        job: &BackfillJobWithId,
+        range: Arc<JobRange>,
+        source: Arc<dyn BlockSource>,
+        concurrency: Arc<AdaptiveConcurrency>,
         cancellation_token: CancellationToken,
     ) -> Result<Worker<Self>> {
         let config = config.read().await;
-        let chain = db.setup_chain(&config.chain).await?;
+        let chain = db.setup_chain(chain).await?;
+
+        // only this job's addresses are searched for, so no matches are written for others
+        let addresses = job.addresses.iter().map(|a| a.0).collect();
+        let matcher = Matcher::new(addresses, config.sync.logs_bloom);
 
         let s = Self {
             job_id: job.id,
-            high: job.high as u64,
-            low: job.low as u64,
+            range,
+            batch_size: config.sync.backfill_batch_size,
+            pipeline: config.sync.pipeline.clone(),
+            method: config.sync.backfill_method,
+            concurrency,
         };
 
-        Worker::new(s, db, &config, chain, provider_factory, cancellation_token).await
+        Ok(Worker::new(
+            s,
+            db,
+            &config,
+            chain,
+            source,
+            Arc::new(std::sync::RwLock::new(matcher)),
+            cancellation_token,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn job(id: i32, address: u8, low: i64, high: i64) -> BackfillJobWithId {
+        BackfillJobWithId {
+            id,
+            addresses: vec![Address(alloy_primitives::Address::repeat_byte(address))],
+            low,
+            high,
+        }
+    }
+
+    fn account(address: u8, last_auth: Option<i64>, requested: bool) -> AccountSchedule {
+        let at = |secs| {
+            chrono::DateTime::from_timestamp(secs, 0)
+                .unwrap()
+                .naive_utc()
+        };
+
+        AccountSchedule {
+            address: Address(alloy_primitives::Address::repeat_byte(address)),
+            last_auth_at: last_auth.map(at),
+            history_requested_at: requested.then(|| at(0)),
+        }
+    }
+
+    fn ids(jobs: Vec<BackfillJobWithId>) -> Vec<i32> {
+        jobs.into_iter().map(|j| j.id).collect()
+    }
+
+    #[tokio::test]
+    async fn test_resize_permits() -> Result<()> {
+        let permits = Arc::new(Permits::new(2));
+        let first = permits.acquire().await?;
+        let second = permits.acquire().await?;
+
+        permits.resize(2, 4);
+        assert_eq!(permits.semaphore.available_permits(), 2);
+
+        // the two held permits can't be taken back yet
+        permits.resize(4, 1);
+        assert_eq!(permits.semaphore.available_permits(), 0);
+
+        // which cancels part of the growth
+        permits.resize(1, 3);
+        assert_eq!(permits.semaphore.available_permits(), 1);
+
+        drop(first);
+        assert_eq!(permits.semaphore.available_permits(), 2);
+        drop(second);
+        assert_eq!(permits.semaphore.available_permits(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_claim_and_steal() {
+        let range = JobRange::new(0..100);
+
+        assert_eq!(range.claim(10), Some(90..100));
+        assert_eq!(range.steal(20), Some(0..45));
+        assert_eq!(range.claim(50), Some(45..90));
+        assert_eq!(range.claim(10), None);
+        assert_eq!(range.steal(1), None);
+    }
+
+    #[test]
+    fn test_steal_respects_min_size() {
+        let range = JobRange::new(0..30);
+
+        assert_eq!(range.steal(20), None);
+        assert_eq!(range.steal(15), Some(0..15));
+        assert_eq!(range.steal(15), None);
+    }
+
+    #[test]
+    fn test_schedule_by_auth_then_recency() {
+        let jobs = vec![
+            job(1, 0x1, 0, 10),
+            job(2, 0x1, 10, 20),
+            job(3, 0x2, 0, 10),
+            job(4, 0x3, 20, 30),
+        ];
+        let accounts = vec![
+            account(0x1, Some(100), false),
+            account(0x2, Some(200), false),
+            account(0x3, None, false),
+        ];
+
+        let scheduled = schedule(jobs, &accounts, BackfillPolicy::Eager);
+        assert_eq!(ids(scheduled), vec![3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn test_schedule_lazy() {
+        let mut shared = job(3, 0x2, 5, 15);
+        shared
+            .addresses
+            .push(Address(alloy_primitives::Address::repeat_byte(0x1)));
+        let jobs = vec![job(1, 0x1, 0, 10), job(2, 0x2, 10, 20), shared];
+        let accounts = vec![account(0x1, None, true), account(0x2, None, false)];
+
+        let scheduled = schedule(jobs, &accounts, BackfillPolicy::Lazy);
+        assert_eq!(ids(scheduled), vec![3, 1]);
+    }
+
+    #[test]
+    fn test_is_on_finish() {
+        assert!(StopStrategy::OnFinish.is_on_finish());
+        assert!(!StopStrategy::Token(CancellationToken::new()).is_on_finish());
     }
 }
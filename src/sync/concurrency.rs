@@ -0,0 +1,227 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::config::SyncConfig;
+
+/// A drop in throughput smaller than this is considered noise
+const TOLERANCE: f64 = 0.05;
+
+/// Flushes this many times slower than the fastest seen are a sign the DB is saturated
+const SATURATED_LATENCY: f64 = 4.0;
+
+/// Backfill concurrency, adjusted at runtime to find the throughput peak
+///
+/// Workers report how many blocks they walk and how long each DB flush takes. On every sample,
+/// blocks/sec is compared with the previous sample: the value keeps moving in the same direction
+/// while throughput improves, and turns around once it drops. Flush latency climbing well above
+/// the fastest seen also turns it down
+#[derive(Debug)]
+pub struct AdaptiveConcurrency {
+    enabled: bool,
+    min: usize,
+    max: usize,
+    current: AtomicUsize,
+
+    /// Totals since the last sample
+    blocks: AtomicU64,
+    flushes: AtomicU64,
+    flush_micros: AtomicU64,
+
+    climb: Mutex<Climb>,
+}
+
+#[derive(Debug)]
+struct Climb {
+    /// Whether the last step was upwards
+    up: bool,
+    last_rate: Option<f64>,
+    best_latency: Option<f64>,
+    blocks_per_sec: f64,
+    flush_latency_ms: f64,
+}
+
+/// Snapshot of the current concurrency, its bounds, and the last measured throughput
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ConcurrencyStatus {
+    pub current: usize,
+    pub min: usize,
+    pub max: usize,
+    pub adaptive: bool,
+    pub blocks_per_sec: f64,
+    pub flush_latency_ms: f64,
+}
+
+impl AdaptiveConcurrency {
+    /// Starts at `backfill_concurrency`, clamped to the configured bounds unless adjustment is
+    /// disabled, in which case it stays fixed at exactly that value
+    pub fn new(config: &SyncConfig) -> Self {
+        let bounds = &config.adaptive_concurrency;
+        let min = bounds.min.max(1);
+        let max = bounds.max.max(min);
+        let current = match bounds.enabled {
+            true => config.backfill_concurrency.clamp(min, max),
+            false => config.backfill_concurrency.max(1),
+        };
+
+        Self {
+            enabled: bounds.enabled,
+            min,
+            max,
+            current: AtomicUsize::new(current),
+            blocks: Default::default(),
+            flushes: Default::default(),
+            flush_micros: Default::default(),
+            climb: Mutex::new(Climb {
+                up: true,
+                last_rate: None,
+                best_latency: None,
+                blocks_per_sec: 0.0,
+                flush_latency_ms: 0.0,
+            }),
+        }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    pub fn record_blocks(&self, blocks: u64) {
+        self.blocks.fetch_add(blocks, Ordering::SeqCst);
+    }
+
+    pub fn record_flush(&self, elapsed: Duration) {
+        self.flushes.fetch_add(1, Ordering::SeqCst);
+        self.flush_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    /// Samples throughput over the last `elapsed`, and takes one step towards the peak
+    /// Returns the new concurrency. Nothing changes while no blocks are being walked
+    pub fn adjust(&self, elapsed: Duration) -> usize {
+        let blocks = self.blocks.swap(0, Ordering::SeqCst);
+        let flushes = self.flushes.swap(0, Ordering::SeqCst);
+        let flush_micros = self.flush_micros.swap(0, Ordering::SeqCst);
+        let current = self.current();
+
+        let mut climb = self.climb.lock().unwrap();
+        let rate = blocks as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        climb.blocks_per_sec = rate;
+        if flushes > 0 {
+            climb.flush_latency_ms = flush_micros as f64 / flushes as f64 / 1000.0;
+        }
+
+        if !self.enabled || blocks == 0 {
+            return current;
+        }
+
+        if climb
+            .last_rate
+            .is_some_and(|last| rate < last * (1.0 - TOLERANCE))
+        {
+            climb.up = !climb.up;
+        }
+        if flushes > 0 {
+            let latency = climb.flush_latency_ms;
+            let best = climb.best_latency.map_or(latency, |best| best.min(latency));
+            if climb.up && latency > best * SATURATED_LATENCY {
+                climb.up = false;
+            }
+            climb.best_latency = Some(best);
+        }
+        climb.last_rate = Some(rate);
+
+        let step = (current / 8).max(1);
+        let next = match climb.up {
+            true => current.saturating_add(step),
+            false => current.saturating_sub(step),
+        }
+        .clamp(self.min, self.max);
+
+        // turn around at the bounds, so the next sample explores the other way
+        if next == current {
+            climb.up = !climb.up;
+        }
+
+        self.current.store(next, Ordering::SeqCst);
+        next
+    }
+
+    pub fn status(&self) -> ConcurrencyStatus {
+        let climb = self.climb.lock().unwrap();
+
+        ConcurrencyStatus {
+            current: self.current(),
+            min: self.min,
+            max: self.max,
+            adaptive: self.enabled,
+            blocks_per_sec: climb.blocks_per_sec,
+            flush_latency_ms: climb.flush_latency_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+
+    fn controller(start: usize, min: usize, max: usize) -> AdaptiveConcurrency {
+        let mut config = Config::for_test().sync;
+        config.backfill_concurrency = start;
+        config.adaptive_concurrency.min = min;
+        config.adaptive_concurrency.max = max;
+        AdaptiveConcurrency::new(&config)
+    }
+
+    fn sample(c: &AdaptiveConcurrency, blocks: u64, flush: Duration) -> usize {
+        c.record_blocks(blocks);
+        c.record_flush(flush);
+        c.adjust(Duration::from_secs(1))
+    }
+
+    #[test]
+    fn test_climbs_while_throughput_improves() {
+        let c = controller(8, 1, 64);
+        let ms = Duration::from_millis(10);
+
+        assert_eq!(sample(&c, 100, ms), 9);
+        assert_eq!(sample(&c, 200, ms), 10);
+        assert_eq!(sample(&c, 300, ms), 11);
+
+        // throughput dropped, so it turns around
+        assert_eq!(sample(&c, 100, ms), 10);
+    }
+
+    #[test]
+    fn test_backs_off_when_db_saturates() {
+        let c = controller(16, 1, 64);
+
+        assert_eq!(sample(&c, 100, Duration::from_millis(10)), 18);
+        assert_eq!(sample(&c, 200, Duration::from_millis(100)), 16);
+    }
+
+    #[test]
+    fn test_stays_within_bounds() {
+        let c = controller(100, 2, 4);
+        assert_eq!(c.current(), 4);
+
+        for i in 1..10 {
+            let next = sample(&c, i * 100, Duration::from_millis(10));
+            assert!((2..=4).contains(&next));
+        }
+    }
+
+    #[test]
+    fn test_idle_keeps_value() {
+        let c = controller(8, 1, 64);
+        assert_eq!(c.adjust(Duration::from_secs(1)), 8);
+        assert_eq!(c.status().current, 8);
+    }
+}
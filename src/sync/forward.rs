@@ -1,19 +1,40 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
-use alloy_primitives::Address;
+use alloy_primitives::{Address, B256};
 use async_trait::async_trait;
-use color_eyre::eyre::Result;
-use reth_provider::HeaderProvider;
-use tokio::sync::mpsc::UnboundedReceiver;
+use color_eyre::eyre::{eyre, Result};
+#[cfg(any(test, feature = "exex"))]
+use futures::{Stream, StreamExt};
+use reth_primitives::Header;
+use tokio::{
+    select,
+    sync::{mpsc::UnboundedReceiver, watch},
+};
 use tokio_util::sync::CancellationToken;
-use tracing::{info, instrument};
+use tracing::{info, instrument, trace, warn};
 
-use super::{RethProviderFactory, SyncJob, Worker};
+use super::{matcher::Matcher, BlockSource, SyncJob, Worker};
 use crate::{
     config::Config,
-    db::{models::Chain, Db},
+    db::{
+        models::{Block, Chain},
+        Db,
+    },
 };
 
+/// Blocks are flushed at least this often, even if few matches were found in them, so that the
+/// number of block hashes written at once stays bounded
+const MAX_UNFLUSHED_BLOCKS: usize = 1000;
+
+/// How often the address filter is persisted while addresses are being added to it
+/// Serializing it takes time proportional to the number of addresses, so it isn't done on every
+/// flush. It is also persisted on shutdown
+const FILTER_PERSIST_INTERVAL: Duration = Duration::from_secs(300);
+
 /// Main sync job
 /// Walks the blockchain forward, from a pre-configured starting block.
 /// Once it reaches the tip, waits continuously for new blocks to process
@@ -25,13 +46,38 @@ pub struct Forward {
     /// Receiver for account registration events
     accounts_rcv: UnboundedReceiver<Address>,
     next_block: u64,
+
+    /// Latest block the source has all the data for, as last seen
+    tip: u64,
+
+    /// Every new tip, as published by the chain's `TipWatcher`
+    /// `None` when following a node's notifications instead, which need no tip
+    tips: Option<watch::Receiver<u64>>,
+
+    /// Hashes of the most recently indexed blocks, used to detect reorgs
+    recent_blocks: BTreeMap<u64, B256>,
+
+    /// Blocks processed since the last flush, whose hashes are not yet persisted
+    unflushed_blocks: Vec<(u64, B256)>,
+
+    /// How many blocks to keep in `recent_blocks`
+    reorg_depth: u64,
+
+    /// How many blocks behind the tip a block is considered safe
+    safe_depth: u64,
+
+    /// Whether addresses were added since the address filter was last persisted
+    filter_dirty: bool,
+
+    /// When the address filter was last persisted, or the sync started
+    filter_persisted_at: Instant,
 }
 
 #[async_trait]
 impl SyncJob for Worker<Forward> {
     #[instrument(name = "forward", skip(self), fields(chain_id = self.chain.chain_id))]
     async fn run(mut self) -> Result<()> {
-        self.inner.next_block = (self.chain.last_known_block as u64) + 1;
+        self.inner.next_block = u64::try_from(self.chain.last_known_block + 1)?;
 
         loop {
             if self.cancellation_token.is_cancelled() {
@@ -40,11 +86,26 @@ impl SyncJob for Worker<Forward> {
 
             self.process_new_accounts().await?;
 
-            let provider = self.provider_factory.get()?;
-            match provider.header_by_number(self.inner.next_block)? {
+            // a header can be available before the rest of its block, so never go past the tip
+            if self.inner.next_block > self.inner.tip {
+                self.inner.tip = *self.tips()?.borrow_and_update();
+            }
+            let header = match self.inner.next_block <= self.inner.tip {
+                true => self.source.header(self.inner.next_block).await?,
+                false => None,
+            };
+
+            match header {
                 // got a block. process it, only flush if needed
                 Some(header) => {
+                    if let Some(fork_block) = self.detect_reorg(&header).await? {
+                        self.rollback(fork_block).await?;
+                        continue;
+                    }
+
                     self.process_block(&header).await?;
+                    let hash = self.hash_of(&header).await?;
+                    self.track_block(header.number, hash);
                     self.maybe_flush().await?;
                     self.inner.next_block += 1;
                 }
@@ -52,43 +113,163 @@ impl SyncJob for Worker<Forward> {
                 // no block found. take the wait chance to flush, and wait for new block
                 None => {
                     self.flush().await?;
-                    self.wait_new_block(self.inner.next_block).await?;
+                    self.inner.tip = self.wait_new_block(self.inner.next_block).await?;
                 }
             }
         }
 
+        self.persist_filter().await?;
         info!("closing");
         Ok(())
     }
 }
 
 impl Worker<Forward> {
+    /// Waits until `block` is available, or until cancellation is requested
+    /// Returns the latest block seen
+    async fn wait_new_block(&mut self, block: u64) -> Result<u64> {
+        trace!(event = "wait", block);
+        let token = self.cancellation_token.clone();
+        let tips = self.tips()?;
+        loop {
+            let latest = *tips.borrow_and_update();
+
+            if latest >= block {
+                trace!("new block(s) found. from: {}, latest: {}", block, latest);
+                return Ok(latest);
+            }
+
+            select! {
+                _ = token.cancelled() => return Ok(latest),
+                changed = tips.changed() => {
+                    if changed.is_err() && !token.is_cancelled() {
+                        return Err(eyre!("tip watcher stopped"));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Tips published by the chain's `TipWatcher`, which `run` needs to know when to wait
+    fn tips(&mut self) -> Result<&mut watch::Receiver<u64>> {
+        self.inner
+            .tips
+            .as_mut()
+            .ok_or_else(|| eyre!("no tip watcher"))
+    }
+
     pub async fn process_new_accounts(&mut self) -> Result<()> {
         while let Ok(address) = self.inner.accounts_rcv.try_recv() {
-            self.addresses.insert(address);
-            self.cuckoo.insert(&address);
+            self.track_address(address);
             self.setup_backfill(address).await?;
+            self.inner.filter_dirty = true;
         }
         Ok(())
     }
 
     /// Create a new job for backfilling history for a new account
     /// before the current sync point
+    /// The job starts at the block the account asked its history to start from, if any
+    /// It never reaches into blocks whose receipts the node pruned
     async fn setup_backfill(&mut self, address: Address) -> Result<()> {
+        let start_block = self
+            .db
+            .get_history_start(self.chain.chain_id, &address.into())
+            .await?
+            .unwrap_or_default()
+            .max(u64::try_from(self.chain.start_block)?)
+            .max(self.source.pruned().await?.complete_from());
+        if start_block >= self.inner.next_block {
+            return Ok(());
+        }
+
         self.db
             .create_backfill_job(
+                self.chain.chain_id,
                 address.into(),
-                self.chain.start_block,
-                self.inner.next_block as i32,
+                start_block,
+                self.inner.next_block,
             )
             .await?;
         Ok(())
     }
 
-    /// if the buffer is sufficiently large, flush it to the database
-    /// and update chain tip
+    /// Checks if the given header builds on top of the last indexed block
+    /// If it doesn't, walks back through recently indexed blocks until one is found that is still
+    /// canonical, and returns it as the fork point
+    async fn detect_reorg(&self, header: &Header) -> Result<Option<u64>> {
+        let Some(parent) = header.number.checked_sub(1) else {
+            return Ok(None);
+        };
+
+        match self.inner.recent_blocks.get(&parent) {
+            Some(hash) if *hash != header.parent_hash => {}
+            // either it matches, or we don't know the parent (e.g.: fresh start)
+            _ => return Ok(None),
+        }
+
+        for (number, hash) in self.inner.recent_blocks.iter().rev() {
+            if self.source.block_hash(*number).await? == Some(*hash) {
+                return Ok(Some(*number));
+            }
+        }
+
+        Err(eyre!(
+            "reorg at block {} is deeper than the {} tracked blocks",
+            header.number,
+            self.inner.recent_blocks.len()
+        ))
+    }
+
+    /// Reverts everything indexed after `fork_block`,
+    /// and rewinds the sync so that the canonical branch is indexed instead
+    async fn rollback(&mut self, fork_block: u64) -> Result<()> {
+        warn!(
+            event = "reorg",
+            fork_block,
+            next_block = self.inner.next_block
+        );
+
+        self.buffer.retain(|m| m.block_number <= fork_block);
+        self.incomplete.retain(|number| *number <= fork_block);
+        self.inner
+            .recent_blocks
+            .retain(|number, _| *number <= fork_block);
+        self.inner
+            .unflushed_blocks
+            .retain(|(number, _)| *number <= fork_block);
+
+        self.db
+            .rollback_chain(self.chain.chain_id, fork_block)
+            .await?;
+        self.inner.next_block = fork_block + 1;
+
+        Ok(())
+    }
+
+    /// Hash of a block, as the source reports it
+    /// Children refer to their parent by this hash, which may not match one recomputed from the
+    /// header, e.g. on chains with header fields alloy doesn't model
+    async fn hash_of(&self, header: &Header) -> Result<B256> {
+        Ok(self
+            .source
+            .block_hash(header.number)
+            .await?
+            .unwrap_or_else(|| header.hash_slow()))
+    }
+
+    /// Remembers the hash of a processed block, for later reorg detection
+    fn track_block(&mut self, number: u64, hash: B256) {
+        self.inner.recent_blocks.insert(number, hash);
+        self.inner.unflushed_blocks.push((number, hash));
+    }
+
+    /// if the buffer is sufficiently large, or enough blocks were processed since the last
+    /// flush, flush it to the database and update chain tip
     pub async fn maybe_flush(&mut self) -> Result<()> {
-        if self.buffer.len() >= self.buffer_capacity {
+        if self.buffer.len() >= self.buffer_capacity
+            || self.inner.unflushed_blocks.len() >= MAX_UNFLUSHED_BLOCKS
+        {
             self.flush().await?;
         }
 
@@ -97,13 +278,67 @@ impl Worker<Forward> {
 
     // empties the buffer and updates chain tip
     pub async fn flush(&mut self) -> Result<()> {
-        let txs = self.drain_buffer();
+        let txs = self.drain_buffer()?;
+
+        let prune_below = self.inner.next_block.saturating_sub(self.inner.reorg_depth);
+        self.inner.recent_blocks = self.inner.recent_blocks.split_off(&prune_below);
+        let blocks = self
+            .inner
+            .unflushed_blocks
+            .drain(..)
+            .filter(|(number, _)| *number >= prune_below)
+            .map(|(number, hash)| {
+                Ok(Block {
+                    chain_id: self.chain.chain_id,
+                    number: i64::try_from(number)?,
+                    hash: hash.into(),
+                })
+            })
+            .collect::<Result<_>>()?;
 
         self.db.create_txs(txs).await?;
+        self.db
+            .create_incomplete_blocks(self.drain_incomplete()?)
+            .await?;
+        self.db
+            .create_blocks(self.chain.chain_id, blocks, prune_below)
+            .await?;
         self.db
             .update_chain(self.chain.chain_id as u64, self.inner.next_block)
             .await?;
 
+        let (safe, finalized) = self.source.finality(self.inner.safe_depth).await?;
+        self.db
+            .update_chain_finality(self.chain.chain_id as u64, safe, finalized)
+            .await?;
+
+        if self.inner.filter_persisted_at.elapsed() >= FILTER_PERSIST_INTERVAL {
+            self.persist_filter().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Persists the address filter, if addresses were added since it last was
+    /// It is stamped with the accounts table as of now, so it's only saved if it holds as many
+    /// addresses, i.e.: if no registration is still on its way to it
+    async fn persist_filter(&mut self) -> Result<()> {
+        if !self.inner.filter_dirty {
+            return Ok(());
+        }
+
+        let stamp = self.db.get_accounts_stamp(self.chain.chain_id).await?;
+        let filter = {
+            let matcher = self.matcher.read().unwrap();
+            if i64::try_from(matcher.address_count())? != stamp.count {
+                return Ok(());
+            }
+            matcher.to_persisted(self.chain.chain_id, &stamp)?
+        };
+
+        self.db.save_address_filter(filter).await?;
+        self.inner.filter_dirty = false;
+        self.inner.filter_persisted_at = Instant::now();
         Ok(())
     }
 }
@@ -113,21 +348,206 @@ impl Forward {
         db: Db,
         config: &Config,
         chain: Chain,
-        provider_factory: Arc<RethProviderFactory>,
+        source: Arc<dyn BlockSource>,
+        tips: Option<watch::Receiver<u64>>,
         accounts_rcv: UnboundedReceiver<Address>,
         cancellation_token: CancellationToken,
     ) -> Result<Worker<Self>> {
-        Worker::new(
+        let reorg_depth = config.sync.reorg_depth;
+        let recent_blocks = db
+            .get_recent_blocks(chain.chain_id, reorg_depth)
+            .await?
+            .into_iter()
+            .map(|b| Ok((u64::try_from(b.number)?, b.hash.0)))
+            .collect::<Result<_>>()?;
+
+        // the global address set, updated in place as new accounts register
+        let matcher = Matcher::load(&db, chain.chain_id, config.sync.logs_bloom).await?;
+
+        Ok(Worker::new(
             Forward {
                 accounts_rcv,
-                next_block: (chain.last_known_block as u64) + 1,
+                next_block: u64::try_from(chain.last_known_block + 1)?,
+                tip: 0,
+                tips,
+                recent_blocks,
+                unflushed_blocks: Vec::new(),
+                reorg_depth,
+                safe_depth: config.sync.safe_depth,
+                filter_dirty: false,
+                filter_persisted_at: Instant::now(),
             },
             db,
             config,
             chain,
-            provider_factory,
+            source,
+            Arc::new(RwLock::new(matcher)),
             cancellation_token,
-        )
-        .await
+        ))
+    }
+}
+
+/// A canonical chain update, as pushed by reth to its Execution Extensions
+/// Mirrors `reth_exex::ExExNotification`, so that the forward sync can be driven (and tested)
+/// without a running node
+#[cfg(any(test, feature = "exex"))]
+#[derive(Debug, Clone)]
+pub enum ChainNotification {
+    Committed {
+        new: Arc<reth_provider::Chain>,
+    },
+    Reorged {
+        old: Arc<reth_provider::Chain>,
+        new: Arc<reth_provider::Chain>,
+    },
+    Reverted {
+        old: Arc<reth_provider::Chain>,
+    },
+}
+
+#[cfg(any(test, feature = "exex"))]
+impl ChainNotification {
+    /// Blocks that are no longer canonical
+    pub fn reverted_chain(&self) -> Option<&Arc<reth_provider::Chain>> {
+        match self {
+            Self::Reorged { old, .. } | Self::Reverted { old } => Some(old),
+            Self::Committed { .. } => None,
+        }
+    }
+
+    /// Blocks that became canonical
+    pub fn committed_chain(&self) -> Option<&Arc<reth_provider::Chain>> {
+        match self {
+            Self::Committed { new } | Self::Reorged { new, .. } => Some(new),
+            Self::Reverted { .. } => None,
+        }
+    }
+}
+
+#[cfg(feature = "exex")]
+impl From<reth_exex::ExExNotification> for ChainNotification {
+    fn from(notification: reth_exex::ExExNotification) -> Self {
+        use reth_exex::ExExNotification::*;
+
+        match notification {
+            ChainCommitted { new } => Self::Committed { new },
+            ChainReorged { old, new } => Self::Reorged { old, new },
+            ChainReverted { old } => Self::Reverted { old },
+        }
+    }
+}
+
+#[cfg(any(test, feature = "exex"))]
+impl Worker<Forward> {
+    /// Drives the forward sync from a stream of chain notifications, instead of polling the source
+    /// After each notification, `finished` is called with the latest indexed block
+    #[instrument(name = "forward", skip_all, fields(chain_id = self.chain.chain_id))]
+    pub async fn follow<S>(
+        mut self,
+        mut notifications: S,
+        mut finished: impl FnMut(u64) + Send,
+    ) -> Result<()>
+    where
+        S: Stream<Item = ChainNotification> + Unpin + Send,
+    {
+        loop {
+            let notification = tokio::select! {
+                _ = self.cancellation_token.cancelled() => break,
+                notification = notifications.next() => match notification {
+                    Some(notification) => notification,
+                    None => break,
+                },
+            };
+
+            self.process_new_accounts().await?;
+
+            if let Some(old) = notification.reverted_chain() {
+                self.revert(old).await?;
+            }
+            if let Some(new) = notification.committed_chain() {
+                self.commit(new).await?;
+            }
+
+            self.flush().await?;
+            // nothing was indexed yet, e.g.: a chain followed from genesis
+            if let Some(last) = self.inner.next_block.checked_sub(1) {
+                finished(last);
+            }
+        }
+
+        self.persist_filter().await?;
+        info!("closing");
+        Ok(())
+    }
+
+    /// Rolls back everything indexed from the first block of `old` onwards
+    async fn revert(&mut self, old: &reth_provider::Chain) -> Result<()> {
+        let first = old.first().number;
+
+        if first < self.inner.next_block {
+            self.rollback(first.saturating_sub(1)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Indexes the blocks of a newly committed chain
+    /// Any blocks missed before it (e.g.: while the indexer was stopped) are read from the source
+    async fn commit(&mut self, new: &reth_provider::Chain) -> Result<()> {
+        while self.inner.next_block < new.first().number {
+            let header = self
+                .source
+                .header(self.inner.next_block)
+                .await?
+                .ok_or_else(|| eyre!("missing header for block {}", self.inner.next_block))?;
+
+            self.process_block(&header).await?;
+            let hash = self.hash_of(&header).await?;
+            self.track_block(header.number, hash);
+            self.maybe_flush().await?;
+            self.inner.next_block += 1;
+        }
+
+        for (block, receipts) in new.blocks_and_receipts() {
+            // already indexed, e.g.: if notifications are replayed after a restart
+            if block.number < self.inner.next_block {
+                continue;
+            }
+
+            // the lock guard must not be held across the flush below
+            {
+                let matcher = self.matcher.read().unwrap();
+                let txs = block.body.iter().zip(&block.senders).zip(receipts);
+                let mut complete = true;
+                for ((tx, sender), receipt) in txs {
+                    // a pruned receipt still leaves the sender and recipient to match
+                    let logs = match receipt {
+                        Some(receipt) => receipt.logs.as_slice(),
+                        None => {
+                            complete = false;
+                            &[]
+                        }
+                    };
+
+                    matcher.match_tx(
+                        block.number,
+                        tx.hash(),
+                        Some(*sender),
+                        tx.to(),
+                        logs,
+                        &mut self.buffer,
+                    );
+                }
+                if !complete {
+                    self.incomplete.push(block.number);
+                }
+            }
+
+            self.track_block(block.number, block.hash());
+            self.maybe_flush().await?;
+            self.inner.next_block = block.number + 1;
+        }
+
+        Ok(())
     }
 }
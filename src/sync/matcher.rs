@@ -0,0 +1,276 @@
+use std::collections::{BTreeSet, HashSet};
+
+use alloy_primitives::{Address, Bloom, BloomInput, Log, B256};
+use color_eyre::eyre::Result;
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use scalable_cuckoo_filter::{DefaultHasher, ScalableCuckooFilter, ScalableCuckooFilterBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::{source::BlockData, utils, Match};
+use crate::db::{
+    models::{AccountsStamp, AddressFilter},
+    Db,
+};
+
+type Filter = ScalableCuckooFilter<Address, DefaultHasher, FilterRng>;
+
+/// Random source for cuckoo filter evictions
+/// The filter's RNG is not persisted, so a deserialized filter needs a way to make a new one
+#[derive(Debug)]
+struct FilterRng(StdRng);
+
+impl Default for FilterRng {
+    fn default() -> Self {
+        Self(StdRng::from_entropy())
+    }
+}
+
+impl RngCore for FilterRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+/// The bits an address sets in a logs bloom, as a padded log topic, as `(byte, mask)` pairs
+/// A fraction of the size of a whole bloom, so that they're cheap to keep and persist for every
+/// address. Addresses whose bits collide repeat one of them
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct TopicBits([(u8, u8); 3]);
+
+impl TopicBits {
+    fn of(bloom: &Bloom) -> Self {
+        let set: Vec<_> = bloom
+            .as_slice()
+            .iter()
+            .enumerate()
+            .flat_map(|(i, byte)| {
+                (0..8)
+                    .map(|bit| 1u8 << bit)
+                    .filter(move |mask| byte & mask != 0)
+                    .map(move |mask| (i as u8, mask))
+            })
+            .collect();
+        let bit = |i: usize| set.get(i).or(set.first()).copied().unwrap_or_default();
+
+        Self([bit(0), bit(1), bit(2)])
+    }
+
+    fn contained_in(&self, bloom: &Bloom) -> bool {
+        let bytes = bloom.as_slice();
+        self.0
+            .iter()
+            .all(|(byte, mask)| bytes[*byte as usize] & mask != 0)
+    }
+}
+
+/// The set of addresses a worker searches for, and the logic to find them in blocks
+/// The forward sync's matcher is persisted as a whole, so that it doesn't have to be rebuilt from
+/// every registered address on each boot
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Matcher {
+    /// Set of addresses to search for
+    addresses: BTreeSet<Address>,
+
+    /// Cuckoo filter for fast address inclusion check
+    cuckoo: Filter,
+
+    /// Bits of each address as a padded log topic, checked against block headers
+    topic_bits: Vec<TopicBits>,
+
+    /// Union of the topic blooms of all addresses, to rule out most blocks with a single check
+    combined_bloom: Bloom,
+
+    /// Whether to skip reading receipts of blocks whose logs bloom can't match any address
+    /// A setting, so it's not persisted along with the rest
+    #[serde(skip)]
+    logs_bloom: bool,
+}
+
+impl Matcher {
+    pub fn new(addresses: Vec<Address>, logs_bloom: bool) -> Self {
+        let cuckoo = ScalableCuckooFilterBuilder::new()
+            .initial_capacity(addresses.len())
+            .rng(FilterRng::default())
+            .finish();
+
+        let mut matcher = Self {
+            addresses: Default::default(),
+            cuckoo,
+            topic_bits: Vec::with_capacity(addresses.len()),
+            combined_bloom: Bloom::default(),
+            logs_bloom,
+        };
+        addresses.into_iter().for_each(|addr| matcher.insert(addr));
+
+        matcher
+    }
+
+    /// Builds a matcher for all addresses registered on a chain
+    /// The whole matcher is restored from the DB if it was persisted for the same set of addresses,
+    /// which is checked without loading them. It is only rebuilt from every address otherwise
+    pub async fn load(db: &Db, chain_id: i32, logs_bloom: bool) -> Result<Self> {
+        let stamp = db.get_accounts_stamp(chain_id).await?;
+
+        let restored = db
+            .get_address_filter(chain_id)
+            .await?
+            .filter(|f| f.accounts_count == stamp.count && f.accounts_latest == stamp.latest)
+            .and_then(|f| bincode::deserialize::<Self>(&f.filter).ok());
+
+        if let Some(mut matcher) = restored {
+            info!(
+                event = "restore_filter",
+                chain_id,
+                addresses = matcher.addresses.len()
+            );
+            matcher.logs_bloom = logs_bloom;
+            return Ok(matcher);
+        }
+
+        let addresses: Vec<_> = db
+            .get_addresses(chain_id)
+            .await?
+            .into_iter()
+            .map(|a| a.0)
+            .collect();
+        info!(
+            event = "rebuild_filter",
+            chain_id,
+            addresses = addresses.len()
+        );
+
+        let matcher = Self::new(addresses, logs_bloom);
+        db.save_address_filter(matcher.to_persisted(chain_id, &stamp)?)
+            .await?;
+        Ok(matcher)
+    }
+
+    /// Serializes the whole matcher, stamped with the accounts it was built from
+    pub fn to_persisted(&self, chain_id: i32, stamp: &AccountsStamp) -> Result<AddressFilter> {
+        Ok(AddressFilter {
+            chain_id,
+            accounts_count: stamp.count,
+            accounts_latest: stamp.latest,
+            filter: bincode::serialize(self)?,
+        })
+    }
+
+    /// Adds an address to the search set
+    pub fn insert(&mut self, address: Address) {
+        if self.addresses.insert(address) {
+            self.cuckoo.insert(&address);
+
+            let bloom = Bloom::from(BloomInput::Raw(address.into_word().as_slice()));
+            self.combined_bloom.accrue_bloom(&bloom);
+            self.topic_bits.push(TopicBits::of(&bloom));
+        }
+    }
+
+    /// The addresses being searched for
+    pub fn addresses(&self) -> impl Iterator<Item = Address> + '_ {
+        self.addresses.iter().copied()
+    }
+
+    /// Number of addresses being searched for
+    pub fn address_count(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Whether a block with the given logs bloom may have logs with any of the addresses as topic
+    /// A block sharing no bits with the combined bloom can't contain any of them. Only those that
+    /// do are checked against each address, since the union alone would also let through blocks
+    /// holding bits of several different addresses
+    pub fn logs_may_match(&self, logs_bloom: &Bloom) -> bool {
+        if !self.logs_bloom {
+            return true;
+        }
+        if (*logs_bloom & self.combined_bloom).is_zero() {
+            return false;
+        }
+
+        self.topic_bits.iter().any(|b| b.contained_in(logs_bloom))
+    }
+
+    /// Collects matches for all transactions of a block
+    /// Transactions whose receipt is missing (e.g.: pruned) are still matched by sender and
+    /// recipient. Returns whether the block was searched in full, i.e.: no receipt was missing,
+    /// or none was needed
+    pub fn match_block(&self, block: &BlockData, matches: &mut Vec<Match>) -> bool {
+        let txs = block.transactions.iter().zip(&block.senders);
+        let mut complete = true;
+
+        for (i, (tx, sender)) in txs.enumerate() {
+            let logs = match &block.receipts {
+                Some(receipts) => match receipts.get(i) {
+                    Some(Some(receipt)) => receipt.logs.as_slice(),
+                    _ => {
+                        complete = false;
+                        &[]
+                    }
+                },
+                None => &[],
+            };
+
+            self.match_tx(
+                block.header.number,
+                tx.hash(),
+                *sender,
+                tx.to(),
+                logs,
+                matches,
+            );
+        }
+
+        complete
+    }
+
+    /// Collects a match for each tracked address involved in a transaction,
+    /// either as sender, recipient, or as an address-like log topic
+    pub fn match_tx(
+        &self,
+        block_number: u64,
+        hash: B256,
+        sender: Option<Address>,
+        to: Option<Address>,
+        logs: &[Log],
+        matches: &mut Vec<Match>,
+    ) {
+        let mut addresses: HashSet<_> = logs
+            .iter()
+            .flat_map(|log| {
+                log.topics()
+                    .iter()
+                    .filter_map(utils::topic_as_address)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        sender.map(|a| addresses.insert(a));
+        to.map(|a| addresses.insert(a));
+
+        addresses
+            .into_iter()
+            .filter(|addr| self.cuckoo.contains(addr))
+            .filter(|addr| self.addresses.contains(addr))
+            .for_each(|address| {
+                matches.push(Match {
+                    address,
+                    block_number,
+                    hash,
+                })
+            });
+    }
+}
@@ -1,32 +1,37 @@
 mod backfill;
+mod concurrency;
 mod forward;
-mod provider;
+mod matcher;
+mod pipeline;
+mod source;
+mod tip;
 mod utils;
 
-use std::{
-    collections::{BTreeSet, HashSet},
-    sync::Arc,
-    time::Duration,
-};
+use std::sync::{Arc, RwLock};
 
 use alloy_primitives::{Address, B256};
 use async_trait::async_trait;
 pub use backfill::{BackfillManager, StopStrategy};
-use color_eyre::eyre::{eyre, Result};
+use color_eyre::eyre::Result;
+pub use concurrency::AdaptiveConcurrency;
+#[cfg(feature = "exex")]
+pub use forward::ChainNotification;
 pub use forward::Forward;
-pub use provider::RethProviderFactory;
-use rand::{rngs::StdRng, SeedableRng};
+use matcher::Matcher;
 use reth_primitives::Header;
-use reth_provider::{BlockNumReader, BlockReader, ReceiptProvider, TransactionsProvider};
-use scalable_cuckoo_filter::{DefaultHasher, ScalableCuckooFilter, ScalableCuckooFilterBuilder};
-use tokio::time::sleep;
+use source::BlockData;
+#[cfg(feature = "exex")]
+pub use source::ProviderSource;
+pub use source::{
+    backfill_source, block_at_timestamp, block_source, BlockSource, RethProviderFactory,
+};
+pub use tip::TipWatcher;
 use tokio_util::sync::CancellationToken;
-use tracing::trace;
 
 use crate::{
     config::Config,
     db::{
-        models::{Chain, CreateTx},
+        models::{Chain, CreateTx, IncompleteBlock},
         Db,
     },
 };
@@ -36,7 +41,8 @@ use crate::{
 pub struct Worker<T: std::fmt::Debug> {
     inner: T,
 
-    provider_factory: Arc<RethProviderFactory>,
+    /// Where block data is read from
+    source: Arc<dyn BlockSource>,
 
     /// DB handle
     db: Db,
@@ -44,15 +50,17 @@ pub struct Worker<T: std::fmt::Debug> {
     /// Chain configuration
     chain: Chain,
 
-    /// Set of addresses to search for
-    addresses: BTreeSet<Address>,
-
-    /// Cuckoo filter for fast address inclusion check
-    cuckoo: ScalableCuckooFilter<Address, DefaultHasher, StdRng>,
+    /// Addresses to search for
+    /// The forward sync searches for all registered addresses, while backfill workers only search
+    /// for those of their job. Shared with the matcher stage of the pipeline, if any
+    matcher: Arc<RwLock<Matcher>>,
 
     /// Buffer holding matches to be written to the database
     buffer: Vec<Match>,
 
+    /// Blocks searched without some of their receipts, to be recorded along with the buffer
+    incomplete: Vec<u64>,
+
     /// Desired buffer capacity, and threshold at which to flush it
     buffer_capacity: usize,
 
@@ -74,111 +82,976 @@ pub trait SyncJob {
 }
 
 impl<T: std::fmt::Debug> Worker<T> {
-    async fn new(
+    fn new(
         inner: T,
         db: Db,
         config: &Config,
         chain: Chain,
-        provider_factory: Arc<RethProviderFactory>,
+        source: Arc<dyn BlockSource>,
+        matcher: Arc<RwLock<Matcher>>,
         cancellation_token: CancellationToken,
-    ) -> Result<Self> {
-        let addresses: BTreeSet<_> = db.get_addresses().await?.into_iter().map(|a| a.0).collect();
-        let mut cuckoo = ScalableCuckooFilterBuilder::new()
-            .initial_capacity(addresses.len())
-            .rng(StdRng::from_entropy())
-            .finish();
-
-        addresses.iter().for_each(|addr| {
-            cuckoo.insert(addr);
-        });
-
-        Ok(Self {
+    ) -> Self {
+        Self {
             inner,
-            provider_factory,
+            source,
             db,
             chain,
-            addresses,
-            cuckoo,
+            matcher,
             buffer: Vec::with_capacity(config.sync.buffer_size),
+            incomplete: Vec::new(),
             buffer_capacity: config.sync.buffer_size,
             cancellation_token,
-        })
+        }
     }
 
-    pub fn drain_buffer(&mut self) -> Vec<CreateTx> {
+    /// Adds an address to the search set
+    fn track_address(&mut self, address: Address) {
+        self.matcher.write().unwrap().insert(address);
+    }
+
+    pub fn drain_buffer(&mut self) -> Result<Vec<CreateTx>> {
         self.buffer
             .drain(..)
-            .map(|m| CreateTx {
-                address: m.address.into(),
-                chain_id: self.chain.chain_id,
-                hash: m.hash.into(),
-                block_number: m.block_number as i32,
+            .map(|m| {
+                Ok(CreateTx {
+                    address: m.address.into(),
+                    chain_id: self.chain.chain_id,
+                    hash: m.hash.into(),
+                    block_number: i64::try_from(m.block_number)?,
+                })
+            })
+            .collect()
+    }
+
+    pub fn drain_incomplete(&mut self) -> Result<Vec<IncompleteBlock>> {
+        self.incomplete
+            .drain(..)
+            .map(|number| {
+                Ok(IncompleteBlock {
+                    chain_id: self.chain.chain_id,
+                    number: i64::try_from(number)?,
+                })
             })
             .collect()
     }
 
-    async fn wait_new_block(&mut self, block: u64) -> Result<()> {
-        trace!(event = "wait", block);
-        loop {
-            let provider = self.provider_factory.get()?;
+    /// Receipts are only read if the header's logs bloom says they may contain a match
+    async fn process_block(&mut self, header: &Header) -> Result<()> {
+        let number = header.number;
+        let logs_may_match = self
+            .matcher
+            .read()
+            .unwrap()
+            .logs_may_match(&header.logs_bloom);
+        let receipts = match logs_may_match {
+            true => Some(self.source.receipts(number).await?),
+            false => None,
+        };
 
-            let latest = provider.last_block_number().unwrap();
+        let block = BlockData {
+            header: header.clone(),
+            transactions: self.source.transactions(number).await?,
+            senders: self.source.senders(number).await?,
+            receipts,
+        };
+        let complete = self
+            .matcher
+            .read()
+            .unwrap()
+            .match_block(&block, &mut self.buffer);
+        if !complete {
+            self.incomplete.push(number);
+        }
 
-            if latest >= block {
-                trace!("new block(s) found. from: {}, latest: {}", block, latest);
-                return Ok(());
-            }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::eyre::eyre;
+    use serial_test::serial;
+    use tokio::{sync::mpsc, time::sleep};
+
+    use std::{ops::RangeInclusive, sync::atomic::Ordering, time::Duration};
+
+    use futures::stream;
+    use reth_primitives::{Receipts, SealedBlock, SealedBlockWithSenders};
+    use reth_provider::ExecutionOutcome;
 
-            drop(provider);
+    use super::{
+        forward::ChainNotification,
+        source::{
+            memory::{FixtureTx, InMemorySource, BLOCK_TIME},
+            mock_rpc::MockRpc,
+            Pruned,
+        },
+        *,
+    };
+    use crate::{
+        config::{BackfillMethod, PipelineConfig, RpcConfig},
+        db::{models::TxWithFinality, types::Finality},
+    };
 
-            sleep(Duration::from_secs(2)).await;
+    fn alice() -> Address {
+        Address::repeat_byte(0xa)
+    }
+
+    fn bob() -> Address {
+        Address::repeat_byte(0xb)
+    }
+
+    fn carol() -> Address {
+        Address::repeat_byte(0xc)
+    }
+
+    fn erc20() -> Address {
+        Address::repeat_byte(0xe)
+    }
+
+    async fn setup(registered: &[Address]) -> Result<(Db, Config, Chain)> {
+        let db = Db::connect_test().await?;
+        let config = Config::for_test();
+        let chain = db.setup_chain(&config.chains[0]).await?;
+
+        for address in registered {
+            db.register(chain.chain_id, (*address).into(), None).await?;
         }
+
+        Ok((db, config, chain))
     }
 
-    async fn process_block(&mut self, header: &Header) -> Result<()> {
-        let provider = self.provider_factory.get()?;
-        let indices = match provider.block_body_indices(header.number)? {
-            Some(indices) => indices,
-            None => return Err(eyre!("err")),
-        };
+    async fn forward(
+        source: Arc<dyn BlockSource>,
+        registered: &[Address],
+        token: CancellationToken,
+    ) -> Result<(Db, i32, Worker<Forward>)> {
+        let (db, config, chain) = setup(registered).await?;
+        let chain_id = chain.chain_id;
+        let (_, accounts_rcv) = mpsc::unbounded_channel();
+        let tips = TipWatcher::new(
+            &config.chains[0],
+            &config.sync,
+            source.clone(),
+            token.clone(),
+        );
+        let tips_rcv = tips.subscribe();
+        tokio::spawn(tips.run());
+        let worker = Forward::new(
+            db.clone(),
+            &config,
+            chain,
+            source,
+            Some(tips_rcv),
+            accounts_rcv,
+            token,
+        )
+        .await?;
 
-        for tx_id in indices.first_tx_num..indices.first_tx_num + indices.tx_count {
-            let tx = match provider.transaction_by_id_no_hash(tx_id)? {
-                Some(tx) => tx,
-                None => continue,
-            };
+        Ok((db, chain_id, worker))
+    }
 
-            let receipt = match provider.receipt(tx_id)? {
-                Some(receipt) => receipt,
-                None => continue,
-            };
+    /// polls the DB until the address has exactly `len` matches
+    async fn wait_for_history(
+        db: &Db,
+        chain_id: i32,
+        address: Address,
+        len: usize,
+    ) -> Result<Vec<TxWithFinality>> {
+        for _ in 0..200 {
+            let history = db
+                .history(chain_id, &address.into(), Finality::Latest)
+                .await?;
+            if history.len() == len {
+                return Ok(history);
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
 
-            let mut addresses: HashSet<_> = receipt
-                .logs
+        Err(eyre!("timed out waiting for {} matches", len))
+    }
+
+    /// Block numbers of the address's matches, once it has exactly `len` of them
+    async fn history_blocks(
+        db: &Db,
+        chain_id: i32,
+        address: Address,
+        len: usize,
+    ) -> Result<Vec<i64>> {
+        let history = wait_for_history(db, chain_id, address, len).await?;
+        Ok(history.iter().map(|m| m.tx.block_number).collect())
+    }
+
+    /// Runs a backfill manager on the test chain until it has no jobs left
+    async fn run_backfill_to_completion(
+        db: &Db,
+        config: &Config,
+        source: Arc<dyn BlockSource>,
+    ) -> Result<()> {
+        let chain = &config.chains[0];
+        let (_, jobs_rcv) = mpsc::unbounded_channel();
+        BackfillManager::new(
+            db.clone(),
+            config,
+            chain,
+            source,
+            jobs_rcv,
+            StopStrategy::OnFinish,
+        )
+        .run()
+        .await?;
+
+        assert!(db.get_backfill_jobs(chain.chain_id).await?.is_empty());
+        Ok(())
+    }
+
+    /// Packs blocks of the in-memory chain the way reth hands them to an ExEx
+    async fn notified_chain(
+        source: &InMemorySource,
+        blocks: RangeInclusive<u64>,
+    ) -> Result<Arc<reth_provider::Chain>> {
+        let mut sealed = Vec::new();
+        let mut receipts = Vec::new();
+
+        for number in blocks.clone() {
+            let header = source.header(number).await?.unwrap();
+            let body = source
+                .transactions(number)
+                .await?
                 .into_iter()
-                .flat_map(|log| {
-                    log.topics()
-                        .iter()
-                        .filter_map(utils::topic_as_address)
-                        .collect::<Vec<_>>()
-                })
+                .map(|tx| tx.with_hash())
                 .collect();
 
-            tx.recover_signer().map(|a| addresses.insert(a));
-            tx.to().map(|a| addresses.insert(a));
+            sealed.push(SealedBlockWithSenders {
+                block: SealedBlock {
+                    header: header.seal_slow(),
+                    body,
+                    ..Default::default()
+                },
+                senders: source
+                    .senders(number)
+                    .await?
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            });
+            receipts.push(source.receipts(number).await?);
+        }
 
-            addresses
-                .into_iter()
-                .filter(|addr| self.cuckoo.contains(addr))
-                .filter(|addr| self.addresses.contains(addr))
-                .for_each(|address| {
-                    self.buffer.push(Match {
-                        address,
-                        block_number: header.number,
-                        hash: tx.hash(),
-                    })
-                });
+        let outcome = ExecutionOutcome {
+            receipts: Receipts {
+                receipt_vec: receipts,
+            },
+            first_block: *blocks.start(),
+            ..Default::default()
+        };
+
+        Ok(Arc::new(reth_provider::Chain::new(sealed, outcome, None)))
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_process_block() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        let block = source.push_block(vec![
+            FixtureTx::transfer(alice(), carol()),
+            FixtureTx::transfer(carol(), bob()),
+            FixtureTx::erc20_transfer(carol(), erc20(), carol(), alice()),
+            FixtureTx::transfer(carol(), carol()),
+        ]);
+        let (_db, _chain_id, mut worker) =
+            forward(source.clone(), &[alice(), bob()], CancellationToken::new()).await?;
+
+        let header = source.header(block).await?.unwrap();
+        worker.process_block(&header).await?;
+
+        let matches: Vec<_> = worker.buffer.iter().map(|m| m.address).collect();
+        assert_eq!(matches, vec![alice(), bob(), alice()]);
+        assert!(worker.buffer.iter().all(|m| m.block_number == block));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_process_block_skips_receipts_by_bloom() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        let unrelated = source.push_block(vec![
+            FixtureTx::transfer(alice(), carol()),
+            FixtureTx::erc20_transfer(carol(), erc20(), carol(), bob()),
+        ]);
+        let related = source.push_block(vec![FixtureTx::erc20_transfer(
+            carol(),
+            erc20(),
+            carol(),
+            alice(),
+        )]);
+        let (_db, _chain_id, mut worker) =
+            forward(source.clone(), &[alice()], CancellationToken::new()).await?;
+
+        // alice is still matched as a sender, without reading receipts
+        let header = source.header(unrelated).await?.unwrap();
+        worker.process_block(&header).await?;
+        assert_eq!(source.receipt_reads.load(Ordering::SeqCst), 0);
+
+        let header = source.header(related).await?.unwrap();
+        worker.process_block(&header).await?;
+        assert_eq!(source.receipt_reads.load(Ordering::SeqCst), 1);
+
+        let matches: Vec<_> = worker
+            .buffer
+            .iter()
+            .map(|m| (m.address, m.block_number))
+            .collect();
+        assert_eq!(matches, vec![(alice(), unrelated), (alice(), related)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_process_block_without_receipts() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        let block = source.push_block(vec![
+            FixtureTx::transfer(alice(), carol()),
+            FixtureTx::erc20_transfer(carol(), erc20(), carol(), alice()),
+        ]);
+        source.set_pruned(Pruned {
+            receipts: Some(block),
+            ..Default::default()
+        });
+        let (db, chain_id, mut worker) =
+            forward(source.clone(), &[alice()], CancellationToken::new()).await?;
+
+        // alice is still matched as a sender, and the block is reported as incomplete
+        let header = source.header(block).await?.unwrap();
+        worker.process_block(&header).await?;
+        let matches: Vec<_> = worker.buffer.iter().map(|m| m.address).collect();
+        assert_eq!(matches, vec![alice()]);
+
+        worker.flush().await?;
+        assert_eq!(
+            db.get_incomplete_ranges(chain_id).await?,
+            vec![(block, block)]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_forward_reorg() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+
+        let token = CancellationToken::new();
+        let (db, chain_id, worker) = forward(source.clone(), &[alice()], token.clone()).await?;
+        let handle = tokio::spawn(worker.run());
+
+        let before = wait_for_history(&db, chain_id, alice(), 2).await?;
+
+        // replace block 2 with an empty one, and add a block 3 with two new matches
+        source.reorg(1);
+        source.push_block(vec![]);
+        source.push_block(vec![
+            FixtureTx::transfer(alice(), carol()),
+            FixtureTx::transfer(carol(), alice()),
+        ]);
+
+        let after = wait_for_history(&db, chain_id, alice(), 3).await?;
+        token.cancel();
+        handle.await??;
+
+        let blocks: Vec<_> = after.iter().map(|m| m.tx.block_number).collect();
+        assert_eq!(blocks, vec![1, 3, 3]);
+        assert!(after.iter().all(|m| m.tx.hash.0 != before[1].tx.hash.0));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_forward_stops_at_complete_blocks() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        for _ in 0..3 {
+            source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
         }
+        // blocks 2 and 3 only have headers so far
+        source.set_complete(Some(1));
+
+        let token = CancellationToken::new();
+        let (db, chain_id, worker) = forward(source.clone(), &[alice()], token.clone()).await?;
+        let handle = tokio::spawn(worker.run());
+
+        wait_for_history(&db, chain_id, alice(), 1).await?;
+        assert!(!handle.is_finished());
+
+        source.set_complete(None);
+        let history = wait_for_history(&db, chain_id, alice(), 3).await?;
+        token.cancel();
+        handle.await??;
+
+        let blocks: Vec<_> = history.iter().map(|m| m.tx.block_number).collect();
+        assert_eq!(blocks, vec![1, 2, 3]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_follow_notifications() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        for _ in 0..3 {
+            source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        }
+        let first = notified_chain(&source, 1..=2).await?;
+        let old = notified_chain(&source, 3..=3).await?;
+
+        // block 3 gets replaced by a 3' with two matches
+        source.reorg(2);
+        source.push_block(vec![
+            FixtureTx::transfer(carol(), alice()),
+            FixtureTx::transfer(alice(), bob()),
+        ]);
+        let new = notified_chain(&source, 3..=3).await?;
+
+        let notifications = stream::iter(vec![
+            ChainNotification::Committed { new: first },
+            ChainNotification::Committed { new: old.clone() },
+            ChainNotification::Reorged {
+                old,
+                new: new.clone(),
+            },
+        ]);
+
+        let (db, chain_id, worker) =
+            forward(source.clone(), &[alice()], CancellationToken::new()).await?;
+        let mut finished = Vec::new();
+        worker
+            .follow(notifications, |height| finished.push(height))
+            .await?;
+
+        assert_eq!(finished, vec![2, 3, 3]);
+        let history = db
+            .history(chain_id, &alice().into(), Finality::Latest)
+            .await?;
+        let blocks: Vec<_> = history.iter().map(|m| m.tx.block_number).collect();
+        assert_eq!(blocks, vec![1, 2, 3, 3]);
+        let new_txs = &new.blocks()[&3].body;
+        assert!(history[2..]
+            .iter()
+            .all(|m| new_txs.iter().any(|tx| tx.hash() == m.tx.hash.0)));
+
+        // after a restart, reverting 3' leaves only the first two blocks
+        let config = Config::for_test();
+        let chain = db.setup_chain(&config.chains[0]).await?;
+        let (_, accounts_rcv) = mpsc::unbounded_channel();
+        let worker = Forward::new(
+            db.clone(),
+            &config,
+            chain,
+            source,
+            None,
+            accounts_rcv,
+            CancellationToken::new(),
+        )
+        .await?;
+        worker
+            .follow(
+                stream::iter(vec![ChainNotification::Reverted { old: new }]),
+                |_| {},
+            )
+            .await?;
+
+        let history = db
+            .history(chain_id, &alice().into(), Finality::Latest)
+            .await?;
+        assert_eq!(history.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_follow_without_receipts() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        source.push_block(vec![FixtureTx::transfer(carol(), carol())]);
+        source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        source.set_pruned(Pruned {
+            receipts: Some(2),
+            ..Default::default()
+        });
+        let new = notified_chain(&source, 1..=2).await?;
+
+        let (db, chain_id, worker) =
+            forward(source.clone(), &[alice()], CancellationToken::new()).await?;
+        worker
+            .follow(
+                stream::iter(vec![ChainNotification::Committed { new }]),
+                |_| {},
+            )
+            .await?;
+
+        let history = wait_for_history(&db, chain_id, alice(), 1).await?;
+        assert_eq!(history[0].tx.block_number, 2);
+        assert_eq!(db.get_incomplete_ranges(chain_id).await?, vec![(1, 2)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_follow_before_first_block() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        source.push_block(vec![]);
+        let old = notified_chain(&source, 1..=1).await?;
+
+        // a chain followed from genesis, with nothing indexed yet
+        let (db, config, mut chain) = setup(&[]).await?;
+        chain.last_known_block = -1;
+        let (_, accounts_rcv) = mpsc::unbounded_channel();
+        let worker = Forward::new(
+            db,
+            &config,
+            chain,
+            source,
+            None,
+            accounts_rcv,
+            CancellationToken::new(),
+        )
+        .await?;
+
+        let mut finished = Vec::new();
+        worker
+            .follow(
+                stream::iter(vec![ChainNotification::Reverted { old }]),
+                |height| finished.push(height),
+            )
+            .await?;
+        assert!(finished.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_follow_catches_up_from_source() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        for _ in 0..4 {
+            source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        }
+
+        // the node only notifies from block 3 onwards, e.g. after the indexer was stopped
+        let notifications = stream::iter(vec![ChainNotification::Committed {
+            new: notified_chain(&source, 3..=4).await?,
+        }]);
+
+        let (db, chain_id, worker) =
+            forward(source.clone(), &[alice()], CancellationToken::new()).await?;
+        worker.follow(notifications, |_| {}).await?;
+
+        let history = db
+            .history(chain_id, &alice().into(), Finality::Latest)
+            .await?;
+        let blocks: Vec<_> = history.iter().map(|m| m.tx.block_number).collect();
+        assert_eq!(blocks, vec![1, 2, 3, 4]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backfill_from_era1() -> Result<()> {
+        // the archive has matches in blocks 1 to 3, and the node only in blocks 4 and 5
+        let archived = InMemorySource::default();
+        let node = Arc::new(InMemorySource::default());
+        for _ in 0..3 {
+            archived.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+            node.push_block(vec![]);
+        }
+        for _ in 0..2 {
+            node.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        }
+
+        let dir = tempfile::tempdir()?;
+        archived
+            .write_era1(&dir.path().join("test-00000-00000000.era1"), 0..=3)
+            .await?;
+
+        let (db, mut config, chain) = setup(&[alice()]).await?;
+        config.chains[0].era1 = Some(dir.path().to_owned());
+        db.create_backfill_job(chain.chain_id, alice().into(), 1, 6)
+            .await?;
+
+        let source = backfill_source(&config.chains[0], node)?;
+        run_backfill_to_completion(&db, &config, source).await?;
+
+        let blocks = history_blocks(&db, chain.chain_id, alice(), 5).await?;
+        assert_eq!(blocks, vec![1, 2, 3, 4, 5]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_forward_over_rpc() -> Result<()> {
+        let memory = Arc::new(InMemorySource::default());
+        memory.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        memory.push_block(vec![FixtureTx::erc20_transfer(
+            carol(),
+            erc20(),
+            carol(),
+            alice(),
+        )]);
+
+        let mut chain = Config::for_test().chains[0].clone();
+        let mock = MockRpc::serve(memory, chain.chain_id as u64).await?;
+        chain.reth = None;
+        chain.rpc = Some(RpcConfig {
+            url: mock.url,
+            batch_size: 10,
+            cache_size: 100,
+        });
+        let source = block_source(&chain).await?;
+
+        let token = CancellationToken::new();
+        let (db, chain_id, worker) = forward(source, &[alice()], token.clone()).await?;
+        let handle = tokio::spawn(worker.run());
+
+        let history = wait_for_history(&db, chain_id, alice(), 2).await?;
+        token.cancel();
+        handle.await??;
+
+        let blocks: Vec<_> = history.iter().map(|m| m.tx.block_number).collect();
+        assert_eq!(blocks, vec![1, 2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backfill() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        for _ in 0..3 {
+            source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        }
+
+        let (db, config, chain) = setup(&[alice()]).await?;
+        db.create_backfill_job(chain.chain_id, alice().into(), 1, 3)
+            .await?;
+
+        run_backfill_to_completion(&db, &config, source).await?;
+
+        let blocks = history_blocks(&db, chain.chain_id, alice(), 2).await?;
+        assert_eq!(blocks, vec![1, 2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backfill_in_batches() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        for i in 0..5 {
+            match i % 2 {
+                0 => source.push_block(vec![FixtureTx::transfer(alice(), carol())]),
+                _ => source.push_block(vec![FixtureTx::erc20_transfer(
+                    carol(),
+                    erc20(),
+                    carol(),
+                    alice(),
+                )]),
+            };
+        }
+
+        let (db, mut config, chain) = setup(&[alice()]).await?;
+        config.sync.backfill_batch_size = 2;
+        config.sync.backfill_concurrency = 2;
+        config.sync.min_job_size = 1;
+        config.sync.buffer_size = 1;
+        config.sync.pipeline = PipelineConfig {
+            readers: 3,
+            matchers: 2,
+            writers: 2,
+            queue_size: 1,
+        };
+        db.create_backfill_job(chain.chain_id, alice().into(), 1, 6)
+            .await?;
+
+        run_backfill_to_completion(&db, &config, source.clone()).await?;
+
+        let blocks = history_blocks(&db, chain.chain_id, alice(), 5).await?;
+        assert_eq!(blocks, vec![1, 2, 3, 4, 5]);
+
+        // only blocks with an ERC20 transfer to alice had their receipts read
+        assert_eq!(source.receipt_reads.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backfill_steals_from_running_jobs() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        for _ in 0..40 {
+            source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        }
+        source.set_read_delay(Duration::from_millis(20));
+
+        // bob's job is done long before alice's, whose worker is then helped by bob's
+        let (db, mut config, chain) = setup(&[alice(), bob()]).await?;
+        config.sync.backfill_concurrency = 2;
+        config.sync.backfill_batch_size = 1;
+        config.sync.min_job_size = 5;
+        db.create_backfill_job(chain.chain_id, alice().into(), 1, 41)
+            .await?;
+        db.create_backfill_job(chain.chain_id, bob().into(), 41, 42)
+            .await?;
+
+        run_backfill_to_completion(&db, &config, source).await?;
+
+        wait_for_history(&db, chain.chain_id, alice(), 40).await?;
+
+        // a single worker would have covered alice's range in one contiguous walk
+        let coverage = db.get_coverage(chain.chain_id, &alice().into()).await?;
+        assert!(coverage.len() > 1);
+        assert_eq!(coverage.first().unwrap().low, 1);
+        assert_eq!(coverage.last().unwrap().high, 41);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backfill_only_matches_job_addresses() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        for _ in 0..3 {
+            source.push_block(vec![FixtureTx::transfer(alice(), bob())]);
+        }
+
+        // bob is registered, but has no job covering these blocks
+        let (db, config, chain) = setup(&[alice(), bob()]).await?;
+        db.create_backfill_job(chain.chain_id, alice().into(), 1, 3)
+            .await?;
+
+        run_backfill_to_completion(&db, &config, source).await?;
+
+        wait_for_history(&db, chain.chain_id, alice(), 2).await?;
+        let history = db
+            .history(chain.chain_id, &bob().into(), Finality::Latest)
+            .await?;
+        assert!(history.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backfill_contract_from_deployment() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        source.push_block(vec![FixtureTx::transfer(bob(), carol())]);
+        let deployed = source.push_block(vec![FixtureTx::deploy(carol(), erc20())]);
+        source.push_block(vec![FixtureTx::transfer(carol(), erc20())]);
+
+        let (db, config, chain) = setup(&[bob(), erc20()]).await?;
+        let chain_id = chain.chain_id;
+        db.create_backfill_job(chain_id, erc20().into(), 1, 5)
+            .await?;
+        db.create_backfill_job(chain_id, bob().into(), 1, 5).await?;
+
+        run_backfill_to_completion(&db, &config, source).await?;
+
+        // the contract's job was cut at its deployment, bob's was left as is
+        let erc20_coverage = db.get_coverage(chain_id, &erc20().into()).await?;
+        assert_eq!(erc20_coverage.first().unwrap().low, deployed as i64);
+        let bob_coverage = db.get_coverage(chain_id, &bob().into()).await?;
+        assert_eq!(bob_coverage.first().unwrap().low, 1);
+
+        assert_eq!(
+            db.get_history_start(chain_id, &erc20().into()).await?,
+            Some(deployed)
+        );
+        assert_eq!(db.get_history_start(chain_id, &bob().into()).await?, None);
+        assert!(db.get_unchecked_deployments(chain_id).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_block_at_timestamp() -> Result<()> {
+        let source = InMemorySource::default();
+        for _ in 0..4 {
+            source.push_block(vec![]);
+        }
+
+        assert_eq!(block_at_timestamp(&source, 0, 1..5).await?, 1);
+        assert_eq!(block_at_timestamp(&source, 2 * BLOCK_TIME, 1..5).await?, 2);
+        assert_eq!(
+            block_at_timestamp(&source, 2 * BLOCK_TIME + 1, 1..5).await?,
+            3
+        );
+        assert_eq!(block_at_timestamp(&source, 10 * BLOCK_TIME, 1..5).await?, 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_address_filter_persistence() -> Result<()> {
+        let (db, config, chain) = setup(&[alice()]).await?;
+        let chain_id = chain.chain_id;
+        let logs_bloom = config.sync.logs_bloom;
+        let matches = |matcher: &Matcher, address| {
+            let mut matches = Vec::new();
+            matcher.match_tx(1, B256::ZERO, Some(address), None, &[], &mut matches);
+            matches.len()
+        };
+
+        // first boot builds and persists the matcher, stamped like the accounts table
+        let matcher = Matcher::load(&db, chain_id, logs_bloom).await?;
+        let persisted = db.get_address_filter(chain_id).await?.unwrap();
+        let stamp = db.get_accounts_stamp(chain_id).await?;
+        assert_eq!(persisted.accounts_count, 1);
+        assert_eq!(
+            (persisted.accounts_count, persisted.accounts_latest),
+            (stamp.count, stamp.latest)
+        );
+        assert_eq!(matches(&matcher, alice()), 1);
+
+        // an unchanged address set restores it as persisted, without rebuilding it from the
+        // accounts. carol only makes it in if it was restored
+        let stored =
+            Matcher::new(vec![alice(), carol()], logs_bloom).to_persisted(chain_id, &stamp)?;
+        db.save_address_filter(stored).await?;
+        let matcher = Matcher::load(&db, chain_id, logs_bloom).await?;
+        assert_eq!(matches(&matcher, carol()), 1);
+
+        // a corrupted one is rebuilt, and persisted again
+        let mut corrupted = db.get_address_filter(chain_id).await?.unwrap();
+        corrupted.filter.truncate(8);
+        db.save_address_filter(corrupted).await?;
+        let matcher = Matcher::load(&db, chain_id, logs_bloom).await?;
+        assert_eq!(matches(&matcher, alice()), 1);
+        assert_eq!(matches(&matcher, carol()), 0);
+        assert!(db.get_address_filter(chain_id).await?.unwrap().filter.len() > 8);
+
+        // a new account invalidates it
+        db.register(chain_id, bob().into(), None).await?;
+        let matcher = Matcher::load(&db, chain_id, logs_bloom).await?;
+        let persisted = db.get_address_filter(chain_id).await?.unwrap();
+        assert_eq!(persisted.accounts_count, 2);
+        assert_eq!(matches(&matcher, bob()), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_address_filter_persisted_on_shutdown() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        source.push_block(vec![]);
+        let new = notified_chain(&source, 1..=1).await?;
+
+        let (db, config, chain) = setup(&[alice()]).await?;
+        let chain_id = chain.chain_id;
+        let (accounts_tx, accounts_rcv) = mpsc::unbounded_channel();
+        let worker = Forward::new(
+            db.clone(),
+            &config,
+            chain,
+            source,
+            None,
+            accounts_rcv,
+            CancellationToken::new(),
+        )
+        .await?;
+
+        // bob registers while following, long before the matcher is due to be persisted
+        db.register(chain_id, bob().into(), None).await?;
+        accounts_tx.send(bob())?;
+        worker
+            .follow(
+                stream::iter(vec![ChainNotification::Committed { new }]),
+                |_| {},
+            )
+            .await?;
+
+        let persisted = db.get_address_filter(chain_id).await?.unwrap();
+        let stamp = db.get_accounts_stamp(chain_id).await?;
+        assert_eq!(
+            (persisted.accounts_count, persisted.accounts_latest),
+            (stamp.count, stamp.latest)
+        );
+        assert_eq!(persisted.accounts_count, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backfill_stops_at_pruned_blocks() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        for _ in 0..5 {
+            source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        }
+        source.set_pruned(Pruned {
+            receipts: Some(2),
+            ..Default::default()
+        });
+
+        let (db, config, chain) = setup(&[alice()]).await?;
+        db.create_backfill_job(chain.chain_id, alice().into(), 1, 6)
+            .await?;
+        db.create_backfill_job(chain.chain_id, bob().into(), 1, 3)
+            .await?;
+
+        run_backfill_to_completion(&db, &config, source).await?;
+
+        // blocks 1 and 2 were never searched, for either job
+        let blocks = history_blocks(&db, chain.chain_id, alice(), 3).await?;
+        assert_eq!(blocks, vec![3, 4, 5]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_backfill_with_history_index() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        source.push_block(vec![FixtureTx::transfer(alice(), carol())]);
+        source.push_block(vec![FixtureTx::transfer(bob(), carol())]);
+        source.push_block(vec![FixtureTx::erc20_transfer(
+            carol(),
+            erc20(),
+            carol(),
+            alice(),
+        )]);
+        source.push_block(vec![FixtureTx::transfer(bob(), carol())]);
+        source.push_block(vec![FixtureTx::transfer(carol(), alice())]);
+
+        let (db, mut config, chain) = setup(&[alice()]).await?;
+        config.sync.backfill_method = BackfillMethod::HistoryIndex;
+        config.sync.backfill_batch_size = 2;
+        config.sync.buffer_size = 1;
+        db.create_backfill_job(chain.chain_id, alice().into(), 1, 6)
+            .await?;
+
+        run_backfill_to_completion(&db, &config, source.clone()).await?;
+
+        let blocks = history_blocks(&db, chain.chain_id, alice(), 3).await?;
+        assert_eq!(blocks, vec![1, 3, 5]);
+
+        // blocks 2 and 4 were never read
+        assert_eq!(source.tx_reads.load(Ordering::SeqCst), 3);
+
+        let coverage = db.get_coverage(chain.chain_id, &alice().into()).await?;
+        let ranges: Vec<_> = coverage
+            .iter()
+            .map(|c| (c.method.as_str(), c.low, c.high))
+            .collect();
+        assert_eq!(ranges, vec![("history_index", 1, 6)]);
 
         Ok(())
     }
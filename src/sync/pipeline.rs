@@ -0,0 +1,188 @@
+use std::{future::Future, ops::Range, sync::Arc, time::Instant};
+
+use color_eyre::eyre::{Report, Result};
+use futures::{
+    stream::{self, FuturesOrdered},
+    StreamExt,
+};
+use reth_primitives::Header;
+use tokio::{sync::mpsc, task};
+
+use super::{source::read_candidates, AdaptiveConcurrency, Worker};
+use crate::config::{BackfillMethod, PipelineConfig};
+
+impl<T: std::fmt::Debug> Worker<T> {
+    /// Processes ranges of blocks through three concurrent stages, connected by bounded queues:
+    ///   - readers fetch ranges ahead of the matchers
+    ///   - matchers search them for tracked addresses, on blocking threads
+    ///   - writers insert the matches into the DB, whenever the buffer fills up
+    ///
+    /// With `BackfillMethod::HistoryIndex`, readers skip blocks that the source's account history
+    /// index and logs bloom both rule out, wherever the source has such an index
+    ///
+    /// Every stage keeps ranges in their original order. After each write, `checkpoint` is called
+    /// with the last range it covers, so all ranges up to that one are done, along with the least
+    /// thorough method used to search them
+    /// On cancellation, no new ranges are read, but those already read are still written
+    /// Ranges are pulled lazily, as readers get to them
+    /// Blocks walked and flush latency are reported to `concurrency`
+    pub(super) async fn pipeline<I, F, Fut>(
+        &mut self,
+        ranges: I,
+        config: &PipelineConfig,
+        method: BackfillMethod,
+        concurrency: &Arc<AdaptiveConcurrency>,
+        mut checkpoint: F,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = Range<u64>> + Send + 'static,
+        F: FnMut(Range<u64>, BackfillMethod) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let (blocks_tx, blocks_rx) = mpsc::channel(config.queue_size.max(1));
+        let (matches_tx, mut matches_rx) = mpsc::channel(config.queue_size.max(1));
+
+        // reader stage
+        let source = self.source.clone();
+        let matcher = self.matcher.clone();
+        let token = self.cancellation_token.clone();
+        let readers = config.readers.max(1);
+        let addresses: Arc<Vec<_>> = Arc::new(self.matcher.read().unwrap().addresses().collect());
+        let reader = tokio::spawn(async move {
+            let mut batches = stream::iter(ranges)
+                .map(|range| {
+                    let source = source.clone();
+                    let matcher = matcher.clone();
+                    let addresses = addresses.clone();
+                    async move {
+                        let with_receipts = |header: &Header| {
+                            matcher.read().unwrap().logs_may_match(&header.logs_bloom)
+                        };
+                        let candidates = match method {
+                            BackfillMethod::HistoryIndex => {
+                                source.account_history(&addresses, range.clone()).await?
+                            }
+                            BackfillMethod::Full => None,
+                        };
+
+                        let (blocks, used) = match candidates {
+                            Some(candidates) => (
+                                read_candidates(
+                                    &*source,
+                                    range.clone(),
+                                    &candidates,
+                                    &with_receipts,
+                                )
+                                .await?,
+                                BackfillMethod::HistoryIndex,
+                            ),
+                            None => (
+                                source.blocks(range.clone(), &with_receipts).await?,
+                                BackfillMethod::Full,
+                            ),
+                        };
+                        Ok::<_, Report>((range, used, blocks))
+                    }
+                })
+                .buffered(readers);
+
+            while let Some(batch) = batches.next().await {
+                if token.is_cancelled() || blocks_tx.send(batch?).await.is_err() {
+                    break;
+                }
+            }
+
+            Ok::<_, Report>(())
+        });
+
+        // matcher stage
+        let matcher = self.matcher.clone();
+        let matchers = config.matchers.max(1);
+        let matching = tokio::spawn(async move {
+            let batches = stream::unfold(blocks_rx, |mut rx| async move {
+                rx.recv().await.map(|batch| (batch, rx))
+            });
+            let mut matched = batches
+                .map(|(range, used, blocks)| {
+                    let matcher = matcher.clone();
+                    task::spawn_blocking(move || {
+                        let matcher = matcher.read().unwrap();
+                        let mut matches = Vec::new();
+                        let incomplete: Vec<_> = blocks
+                            .iter()
+                            .filter(|block| !matcher.match_block(block, &mut matches))
+                            .map(|block| block.header.number)
+                            .collect();
+                        (range, used, matches, incomplete)
+                    })
+                })
+                .buffered(matchers);
+
+            while let Some(batch) = matched.next().await {
+                if matches_tx.send(batch?).await.is_err() {
+                    break;
+                }
+            }
+
+            Ok::<_, Report>(())
+        });
+
+        // writer stage
+        let writers = config.writers.max(1);
+        let mut writes = FuturesOrdered::new();
+        let mut last = None;
+        let mut unwritten = None;
+        while let Some((range, used, matches, incomplete)) = matches_rx.recv().await {
+            concurrency.record_blocks(range.end - range.start);
+            self.buffer.extend(matches);
+            self.incomplete.extend(incomplete);
+            unwritten = unwritten.max(Some(used));
+            if self.buffer.len() >= self.buffer_capacity {
+                let method = unwritten.take().unwrap_or_default();
+                writes.push_back(self.write(range.clone(), method, concurrency.clone())?);
+            }
+            last = Some(range);
+
+            while writes.len() >= writers {
+                if let Some(written) = writes.next().await {
+                    let (range, method) = written?;
+                    checkpoint(range, method).await?;
+                }
+            }
+        }
+
+        if let Some(range) = last {
+            let method = unwritten.unwrap_or_default();
+            writes.push_back(self.write(range, method, concurrency.clone())?);
+        }
+        while let Some(written) = writes.next().await {
+            let (range, method) = written?;
+            checkpoint(range, method).await?;
+        }
+
+        reader.await??;
+        matching.await??;
+
+        Ok(())
+    }
+
+    /// Drains the buffer into a DB insert, to be run concurrently with other stages
+    fn write(
+        &mut self,
+        range: Range<u64>,
+        method: BackfillMethod,
+        concurrency: Arc<AdaptiveConcurrency>,
+    ) -> Result<impl Future<Output = Result<(Range<u64>, BackfillMethod)>> + Send> {
+        let db = self.db.clone();
+        let txs = self.drain_buffer()?;
+        let incomplete = self.drain_incomplete()?;
+
+        Ok(async move {
+            let start = Instant::now();
+            db.create_txs(txs).await?;
+            db.create_incomplete_blocks(incomplete).await?;
+            concurrency.record_flush(start.elapsed());
+            Ok((range, method))
+        })
+    }
+}
@@ -0,0 +1,787 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use alloy_primitives::{Address, Bloom, Bytes, Log, B256, U256};
+use alloy_rlp::Decodable;
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Result};
+use reth_primitives::{
+    BlockBody, Header, Receipt, TransactionSigned, TransactionSignedNoHash, TxType,
+};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+use super::{read_blocks, read_headers, BlockData, BlockSource, Pruned};
+
+/// e2store entry types found in Era1 files
+const VERSION: u16 = 0x3265;
+const COMPRESSED_HEADER: u16 = 0x03;
+const COMPRESSED_BODY: u16 = 0x04;
+const COMPRESSED_RECEIPTS: u16 = 0x05;
+const TOTAL_DIFFICULTY: u16 = 0x06;
+const ACCUMULATOR: u16 = 0x07;
+const BLOCK_INDEX: u16 = 0x3266;
+
+/// Maximum number of blocks in an Era1 file
+const EPOCH_SIZE: usize = 8192;
+
+/// How many decoded blocks to keep around, since each is read several times while being processed
+const CACHE_SIZE: usize = 64;
+
+/// Reads pre-merge blocks from a directory of local Era1 archive files, falling back to another
+/// source (usually reth) for blocks not covered by any of them
+///
+/// Each file is verified the first time it is used: its accumulator must match its headers, and
+/// its first and last blocks must match the fallback's, so that archives of another network or
+/// epoch are never indexed as canonical. Files that can't be read or fail verification are ignored
+#[derive(Debug)]
+pub struct Era1Source {
+    files: Vec<Era1File>,
+    fallback: Arc<dyn BlockSource>,
+    cache: Mutex<VecDeque<Arc<Era1Block>>>,
+}
+
+#[derive(Debug)]
+struct Era1File {
+    path: PathBuf,
+
+    /// First block in the file
+    start: u64,
+
+    /// Absolute position of each block's header entry
+    offsets: Vec<u64>,
+
+    /// Whether the file passed verification
+    verified: OnceCell<bool>,
+}
+
+#[derive(Debug)]
+struct Era1Block {
+    number: u64,
+    hash: B256,
+    header: Header,
+    transactions: Vec<TransactionSigned>,
+    receipts: Vec<Receipt>,
+}
+
+struct Entry {
+    ty: u16,
+    data: Vec<u8>,
+}
+
+impl Era1Source {
+    /// Indexes all `.era1` files in `dir`
+    pub fn new(dir: &Path, fallback: Arc<dyn BlockSource>) -> Result<Self> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "era1") {
+                match Era1File::open(path.clone()) {
+                    Ok(file) => files.push(file),
+                    Err(e) => warn!(file = %path.display(), error = %e, "ignoring era1 file"),
+                }
+            }
+        }
+        files.sort_by_key(|f| f.start);
+
+        Ok(Self {
+            files,
+            fallback,
+            cache: Default::default(),
+        })
+    }
+
+    /// Reads a block from the archive, if a verified file covers it
+    async fn block(&self, number: u64) -> Result<Option<Arc<Era1Block>>> {
+        if let Some(block) = self
+            .cache
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|b| b.number == number)
+        {
+            return Ok(Some(block.clone()));
+        }
+
+        let Some(file) = self.files.iter().find(|f| f.contains(number)) else {
+            return Ok(None);
+        };
+        if !file.verified(&*self.fallback).await {
+            return Ok(None);
+        }
+
+        let block = Arc::new(file.read_block(number)?);
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_SIZE {
+            cache.pop_front();
+        }
+        cache.push_back(block.clone());
+
+        Ok(Some(block))
+    }
+}
+
+#[async_trait]
+impl BlockSource for Era1Source {
+    async fn tip(&self) -> Result<u64> {
+        self.fallback.tip().await
+    }
+
+    async fn header(&self, number: u64) -> Result<Option<Header>> {
+        match self.block(number).await? {
+            Some(block) => Ok(Some(block.header.clone())),
+            None => self.fallback.header(number).await,
+        }
+    }
+
+    async fn block_hash(&self, number: u64) -> Result<Option<B256>> {
+        match self.block(number).await? {
+            Some(block) => Ok(Some(block.hash)),
+            None => self.fallback.block_hash(number).await,
+        }
+    }
+
+    async fn transactions(&self, number: u64) -> Result<Vec<TransactionSignedNoHash>> {
+        match self.block(number).await? {
+            Some(block) => Ok(block.transactions.iter().cloned().map(Into::into).collect()),
+            None => self.fallback.transactions(number).await,
+        }
+    }
+
+    /// Era1 files don't include senders, so they're recovered from the signatures
+    async fn senders(&self, number: u64) -> Result<Vec<Option<Address>>> {
+        match self.block(number).await? {
+            Some(block) => Ok(block
+                .transactions
+                .iter()
+                .map(|tx| tx.recover_signer())
+                .collect()),
+            None => self.fallback.senders(number).await,
+        }
+    }
+
+    async fn receipts(&self, number: u64) -> Result<Vec<Option<Receipt>>> {
+        match self.block(number).await? {
+            Some(block) => Ok(block.receipts.iter().cloned().map(Some).collect()),
+            None => self.fallback.receipts(number).await,
+        }
+    }
+
+    /// Ranges not covered by any archive are read from the fallback in one go
+    async fn blocks(
+        &self,
+        range: Range<u64>,
+        with_receipts: &(dyn Fn(&Header) -> bool + Sync),
+    ) -> Result<Vec<BlockData>> {
+        match self.files.iter().any(|f| f.overlaps(&range)) {
+            true => read_blocks(self, range, with_receipts).await,
+            false => self.fallback.blocks(range, with_receipts).await,
+        }
+    }
+
+    async fn headers(&self, range: Range<u64>) -> Result<Vec<Header>> {
+        match self.files.iter().any(|f| f.overlaps(&range)) {
+            true => read_headers(self, range).await,
+            false => self.fallback.headers(range).await,
+        }
+    }
+
+    /// Archives have no history index, so ranges they cover must be searched block by block
+    async fn account_history(
+        &self,
+        addresses: &[Address],
+        range: Range<u64>,
+    ) -> Result<Option<BTreeSet<u64>>> {
+        match self.files.iter().any(|f| f.overlaps(&range)) {
+            true => Ok(None),
+            false => self.fallback.account_history(addresses, range).await,
+        }
+    }
+
+    /// Archives hold no state, so this is always answered by the fallback
+    async fn has_code(&self, address: Address, number: u64) -> Result<Option<bool>> {
+        self.fallback.has_code(address, number).await
+    }
+
+    /// Receipts the fallback pruned are still found in archives, if they cover all of them
+    async fn pruned(&self) -> Result<Pruned> {
+        let mut pruned = self.fallback.pruned().await?;
+
+        let archived = self.files.iter().fold(0, |end, f| match f.start <= end {
+            true => end.max(f.start + f.offsets.len() as u64),
+            false => end,
+        });
+        if pruned.receipts.is_some_and(|last| last < archived) {
+            pruned.receipts = None;
+        }
+
+        Ok(pruned)
+    }
+
+    async fn finality(&self, safe_depth: u64) -> Result<(u64, u64)> {
+        self.fallback.finality(safe_depth).await
+    }
+
+    async fn transaction_by_hash(&self, hash: B256) -> Result<Option<TransactionSigned>> {
+        self.fallback.transaction_by_hash(hash).await
+    }
+}
+
+impl Era1File {
+    /// Reads the block index at the end of the file
+    fn open(path: PathBuf) -> Result<Self> {
+        let mut file = File::open(&path)?;
+        let len = file.metadata()?.len();
+
+        let version = read_entry(&mut file)?;
+        if version.ty != VERSION {
+            return Err(eyre!("{} is not an e2store file", path.display()));
+        }
+
+        // the index ends with the block count
+        file.seek(SeekFrom::Start(len - 8))?;
+        let count = read_u64(&mut file)?;
+
+        // and starts with an 8-byte entry header, followed by the starting block number and
+        // one offset per block, relative to the start of the index entry
+        let index_start = count
+            .checked_add(3)
+            .and_then(|words| len.checked_sub(words.checked_mul(8)?))
+            .ok_or_else(|| eyre!("invalid block index in {}", path.display()))?;
+        file.seek(SeekFrom::Start(index_start))?;
+        let index = read_entry(&mut file)?;
+        if index.ty != BLOCK_INDEX || index.data.len() as u64 != 8 * (count + 2) {
+            return Err(eyre!("invalid block index in {}", path.display()));
+        }
+
+        let fields: Vec<_> = index
+            .data
+            .chunks_exact(8)
+            .map(|chunk| i64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let start = u64::try_from(fields[0])?;
+        let offsets = fields[1..=count as usize]
+            .iter()
+            .map(|offset| Ok(u64::try_from(index_start as i64 + offset)?))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            path,
+            start,
+            offsets,
+            verified: OnceCell::new(),
+        })
+    }
+
+    fn contains(&self, number: u64) -> bool {
+        number >= self.start && number < self.start + self.offsets.len() as u64
+    }
+
+    fn overlaps(&self, range: &Range<u64>) -> bool {
+        range.start < self.start + self.offsets.len() as u64 && self.start < range.end
+    }
+
+    /// Verifies the file against itself and against `chain`, on first use
+    async fn verified(&self, chain: &dyn BlockSource) -> bool {
+        *self
+            .verified
+            .get_or_init(|| async {
+                match self.verify(chain).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!(file = %self.path.display(), error = %e, "ignoring era1 file");
+                        false
+                    }
+                }
+            })
+            .await
+    }
+
+    /// Checks the file's accumulator against its headers and total difficulties, and the hashes
+    /// of its first and last blocks against those of `chain`
+    async fn verify(&self, chain: &dyn BlockSource) -> Result<()> {
+        if self.offsets.is_empty() {
+            return Err(eyre!("archive has no blocks"));
+        }
+        let records = self.verify_accumulator()?;
+
+        // a consistent archive may still be of another network, or hold another epoch
+        let last = self.start + records.len() as u64 - 1;
+        for (number, (hash, _)) in [
+            (self.start, &records[0]),
+            (last, &records[records.len() - 1]),
+        ] {
+            match chain.block_hash(number).await? {
+                Some(canonical) if canonical == *hash => {}
+                Some(canonical) => {
+                    return Err(eyre!(
+                        "block {} is {} in the archive, but {} in the chain",
+                        number,
+                        hash,
+                        canonical
+                    ))
+                }
+                None => return Err(eyre!("block {} is not in the chain yet", number)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the file's accumulator against its headers and total difficulties
+    /// Returns the hash and total difficulty of each block
+    fn verify_accumulator(&self) -> Result<Vec<(B256, U256)>> {
+        let mut file = BufReader::new(File::open(&self.path)?);
+        file.seek(SeekFrom::Start(self.offsets[0]))?;
+
+        let mut records = Vec::with_capacity(self.offsets.len());
+        for _ in &self.offsets {
+            let header = read_entry(&mut file)?.expect(COMPRESSED_HEADER)?;
+            read_entry(&mut file)?.expect(COMPRESSED_BODY)?;
+            read_entry(&mut file)?.expect(COMPRESSED_RECEIPTS)?;
+            let td = read_entry(&mut file)?.expect(TOTAL_DIFFICULTY)?;
+
+            let header = Header::decode(&mut decompress(&header)?.as_slice())?;
+            records.push((header.hash_slow(), U256::from_le_slice(&td)));
+        }
+
+        let accumulator = read_entry(&mut file)?.expect(ACCUMULATOR)?;
+        let expected = B256::try_from(accumulator.as_slice())?;
+        let actual = accumulator_root(&records);
+
+        if actual != expected {
+            return Err(eyre!(
+                "accumulator mismatch: expected {}, got {}",
+                expected,
+                actual
+            ));
+        }
+
+        Ok(records)
+    }
+
+    fn read_block(&self, number: u64) -> Result<Era1Block> {
+        let mut file = BufReader::new(File::open(&self.path)?);
+        file.seek(SeekFrom::Start(
+            self.offsets[(number - self.start) as usize],
+        ))?;
+
+        let header = read_entry(&mut file)?.expect(COMPRESSED_HEADER)?;
+        let body = read_entry(&mut file)?.expect(COMPRESSED_BODY)?;
+        let receipts = read_entry(&mut file)?.expect(COMPRESSED_RECEIPTS)?;
+
+        let header = Header::decode(&mut decompress(&header)?.as_slice())?;
+        let body = BlockBody::decode(&mut decompress(&body)?.as_slice())?;
+        let receipts = decode_receipts(&decompress(&receipts)?)?;
+
+        if header.number != number {
+            return Err(eyre!(
+                "expected block {} in {}, found {}",
+                number,
+                self.path.display(),
+                header.number
+            ));
+        }
+
+        Ok(Era1Block {
+            number,
+            hash: header.hash_slow(),
+            header,
+            transactions: body.transactions,
+            receipts,
+        })
+    }
+}
+
+impl Entry {
+    /// Returns the entry's data, if it has the expected type
+    fn expect(self, ty: u16) -> Result<Vec<u8>> {
+        if self.ty != ty {
+            return Err(eyre!(
+                "expected e2store entry {:#06x}, found {:#06x}",
+                ty,
+                self.ty
+            ));
+        }
+
+        Ok(self.data)
+    }
+}
+
+/// Reads an e2store entry: a 2-byte type, a 4-byte length, 2 reserved bytes, and the data
+fn read_entry(reader: &mut impl Read) -> Result<Entry> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+
+    let ty = u16::from_le_bytes([header[0], header[1]]);
+    let len = u32::from_le_bytes([header[2], header[3], header[4], header[5]]);
+
+    let mut data = vec![0u8; len as usize];
+    reader.read_exact(&mut data)?;
+
+    Ok(Entry { ty, data })
+}
+
+/// Decodes a block's receipts
+/// Receipts before Byzantium hold the post-transaction state root where later ones hold a status
+/// byte, so they are decoded field by field. The former are taken as successful, as they don't
+/// record a status
+fn decode_receipts(mut buf: &[u8]) -> Result<Vec<Receipt>> {
+    let mut items = rlp_list(&mut buf)?;
+
+    let mut receipts = Vec::new();
+    while !items.is_empty() {
+        let header = alloy_rlp::Header::decode(&mut items)?;
+        let (mut fields, rest) = split(items, header.payload_length)?;
+        items = rest;
+
+        // typed receipts are a string holding the type, followed by the receipt's fields
+        let tx_type = match header.list {
+            true => TxType::Legacy,
+            false => {
+                let (ty, mut rest) = fields.split_first().ok_or_else(|| eyre!("empty receipt"))?;
+                fields = rlp_list(&mut rest)?;
+                TxType::try_from(*ty).map_err(|e| eyre!("{}", e))?
+            }
+        };
+
+        let outcome = Bytes::decode(&mut fields)?;
+        receipts.push(Receipt {
+            tx_type,
+            success: outcome.len() == 32 || outcome[..] == [1],
+            cumulative_gas_used: u64::decode(&mut fields)?,
+            logs: {
+                Bloom::decode(&mut fields)?;
+                Vec::<Log>::decode(&mut fields)?
+            },
+        });
+    }
+
+    Ok(receipts)
+}
+
+/// Reads an RLP list header, returning the list's payload and advancing `buf` past it
+fn rlp_list<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let header = alloy_rlp::Header::decode(buf)?;
+    if !header.list {
+        return Err(eyre!("expected an RLP list"));
+    }
+
+    let (payload, rest) = split(buf, header.payload_length)?;
+    *buf = rest;
+    Ok(payload)
+}
+
+fn split(buf: &[u8], len: usize) -> Result<(&[u8], &[u8])> {
+    if buf.len() < len {
+        return Err(eyre!("truncated RLP item"));
+    }
+    Ok(buf.split_at(len))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    snap::read::FrameDecoder::new(data).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// SSZ `hash_tree_root` of `List[HeaderRecord, EPOCH_SIZE]`,
+/// where `HeaderRecord = Container(block_hash: Bytes32, total_difficulty: uint256)`
+fn accumulator_root(records: &[(B256, U256)]) -> B256 {
+    let mut layer: Vec<[u8; 32]> = records
+        .iter()
+        .map(|(hash, td)| sha256_pair(&hash.0, &td.to_le_bytes::<32>()))
+        .collect();
+
+    // merkleize, padding the tree up to EPOCH_SIZE leaves with zero hashes
+    let mut zero = [0u8; 32];
+    for _ in 0..EPOCH_SIZE.trailing_zeros() {
+        if layer.len() % 2 == 1 {
+            layer.push(zero);
+        }
+        layer = layer
+            .chunks(2)
+            .map(|pair| sha256_pair(&pair[0], &pair[1]))
+            .collect();
+        zero = sha256_pair(&zero, &zero);
+    }
+    let root = layer.first().copied().unwrap_or(zero);
+
+    // mix in the list length
+    let mut length = [0u8; 32];
+    length[..8].copy_from_slice(&(records.len() as u64).to_le_bytes());
+
+    B256::from(sha256_pair(&root, &length))
+}
+
+fn sha256_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Writes blocks into an Era1 file, to use as a test fixture
+#[cfg(test)]
+pub(super) fn write(
+    path: &Path,
+    blocks: Vec<(Header, Vec<TransactionSigned>, Vec<Receipt>)>,
+) -> Result<()> {
+    use std::io::Write;
+
+    use alloy_rlp::Encodable;
+
+    fn entry(out: &mut Vec<u8>, ty: u16, data: &[u8]) {
+        out.extend_from_slice(&ty.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(data);
+    }
+
+    fn compress(value: &impl Encodable) -> Result<Vec<u8>> {
+        let mut rlp = Vec::new();
+        value.encode(&mut rlp);
+
+        let mut encoder = snap::write::FrameEncoder::new(Vec::new());
+        encoder.write_all(&rlp)?;
+        Ok(encoder.into_inner().map_err(|e| eyre!("{}", e))?)
+    }
+
+    let start = blocks.first().map_or(0, |(header, ..)| header.number);
+    let mut out = Vec::new();
+    let mut offsets = Vec::new();
+    let mut records = Vec::new();
+
+    entry(&mut out, VERSION, &[]);
+    for (header, transactions, receipts) in blocks {
+        let td = U256::from(header.number + 1);
+        let body = BlockBody {
+            transactions,
+            ..Default::default()
+        };
+        let receipts: Vec<_> = receipts.into_iter().map(|r| r.with_bloom()).collect();
+
+        offsets.push(out.len() as i64);
+        entry(&mut out, COMPRESSED_HEADER, &compress(&header)?);
+        entry(&mut out, COMPRESSED_BODY, &compress(&body)?);
+        entry(&mut out, COMPRESSED_RECEIPTS, &compress(&receipts)?);
+        entry(&mut out, TOTAL_DIFFICULTY, &td.to_le_bytes::<32>());
+        records.push((header.hash_slow(), td));
+    }
+    entry(&mut out, ACCUMULATOR, accumulator_root(&records).as_slice());
+
+    let index_start = out.len() as i64;
+    let mut index = Vec::new();
+    index.extend_from_slice(&(start as i64).to_le_bytes());
+    for offset in &offsets {
+        index.extend_from_slice(&(offset - index_start).to_le_bytes());
+    }
+    index.extend_from_slice(&(offsets.len() as i64).to_le_bytes());
+    entry(&mut out, BLOCK_INDEX, &index);
+
+    std::fs::write(path, out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::sync::source::memory::{FixtureTx, InMemorySource};
+
+    #[test]
+    fn test_accumulator_root() {
+        let records = [
+            (B256::repeat_byte(0x11), U256::from(1)),
+            (B256::repeat_byte(0x22), U256::from(2)),
+            (B256::repeat_byte(0x33), U256::from(3)),
+        ];
+
+        assert_eq!(
+            accumulator_root(&records).to_string(),
+            "0xd85acc91610b6b103a70fe4a3892e8f7151be898858fe7cb6195230d27b10f3f"
+        );
+        assert_eq!(
+            accumulator_root(&[]).to_string(),
+            "0x4a8c3a07c8d23adc5bac61157555c3c784d53d9bc110c1370809bd23cd93777d"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reads_archive_and_falls_back() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        for _ in 0..5 {
+            source.push_block(vec![FixtureTx::erc20_transfer(
+                Address::repeat_byte(0xa),
+                Address::repeat_byte(0xe),
+                Address::repeat_byte(0xa),
+                Address::repeat_byte(0xb),
+            )]);
+        }
+
+        let dir = tempdir()?;
+        source
+            .write_era1(&dir.path().join("test-00000-00000000.era1"), 0..=3)
+            .await?;
+        let era1 = Era1Source::new(dir.path(), source.clone())?;
+
+        for n in 0..=5 {
+            assert_eq!(era1.header(n).await?, source.header(n).await?);
+            assert_eq!(era1.transactions(n).await?, source.transactions(n).await?);
+            assert_eq!(era1.receipts(n).await?, source.receipts(n).await?);
+        }
+        assert!(era1.block(3).await?.is_some());
+        assert!(era1.block(4).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ignores_archive_of_another_chain() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        let other = InMemorySource::default();
+        for _ in 0..4 {
+            source.push_block(vec![]);
+            other.push_block(vec![FixtureTx::erc20_transfer(
+                Address::repeat_byte(0xa),
+                Address::repeat_byte(0xe),
+                Address::repeat_byte(0xa),
+                Address::repeat_byte(0xb),
+            )]);
+        }
+
+        // a valid archive, just not of the indexed chain
+        let dir = tempdir()?;
+        other
+            .write_era1(&dir.path().join("test-00000-00000000.era1"), 0..=3)
+            .await?;
+
+        let era1 = Era1Source::new(dir.path(), source.clone())?;
+        assert!(era1.block(1).await?.is_none());
+        assert_eq!(era1.header(3).await?, source.header(3).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ignores_unverified_archive() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        source.push_block(vec![]);
+
+        let dir = tempdir()?;
+        let path = dir.path().join("test-00000-00000000.era1");
+        source.write_era1(&path, 0..=1).await?;
+
+        // corrupt the accumulator, which sits right before the block index
+        let mut bytes = std::fs::read(&path)?;
+        let accumulator = bytes.len() - 8 * 5 - 32;
+        bytes[accumulator] ^= 0xff;
+        std::fs::write(&path, bytes)?;
+
+        let era1 = Era1Source::new(dir.path(), source.clone())?;
+        assert!(era1.block(1).await?.is_none());
+        assert_eq!(era1.header(1).await?, source.header(1).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skips_malformed_archive() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        source.push_block(vec![]);
+
+        let dir = tempdir()?;
+        source
+            .write_era1(&dir.path().join("test-00000-00000000.era1"), 0..=1)
+            .await?;
+        std::fs::write(
+            dir.path().join("test-00001-00000000.era1"),
+            b"not an archive",
+        )?;
+
+        let era1 = Era1Source::new(dir.path(), source.clone())?;
+        assert!(era1.block(1).await?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decodes_pre_byzantium_receipts() -> Result<()> {
+        use alloy_rlp::Encodable;
+
+        let log = Log::new_unchecked(
+            Address::repeat_byte(0xe),
+            vec![B256::repeat_byte(0x1)],
+            Bytes::from_static(&[1, 2]),
+        );
+
+        // [post-state root, cumulative gas used, bloom, logs]
+        let mut fields = Vec::new();
+        B256::repeat_byte(0xaa).encode(&mut fields);
+        21000u64.encode(&mut fields);
+        Bloom::default().encode(&mut fields);
+        vec![log.clone()].encode(&mut fields);
+        let mut receipts = Vec::new();
+        alloy_rlp::Header {
+            list: true,
+            payload_length: fields.len() + alloy_rlp::length_of_length(fields.len()),
+        }
+        .encode(&mut receipts);
+        alloy_rlp::Header {
+            list: true,
+            payload_length: fields.len(),
+        }
+        .encode(&mut receipts);
+        receipts.extend_from_slice(&fields);
+
+        assert_eq!(
+            decode_receipts(&receipts)?,
+            vec![Receipt {
+                tx_type: TxType::Legacy,
+                success: true,
+                cumulative_gas_used: 21000,
+                logs: vec![log.clone()],
+            }]
+        );
+
+        // receipts with a status, typed or not, decode as well
+        let receipts = vec![
+            Receipt {
+                tx_type: TxType::Legacy,
+                success: false,
+                cumulative_gas_used: 21000,
+                logs: vec![],
+            },
+            Receipt {
+                tx_type: TxType::Eip1559,
+                success: true,
+                cumulative_gas_used: 63000,
+                logs: vec![log],
+            },
+        ];
+        let mut encoded = Vec::new();
+        receipts
+            .iter()
+            .map(|r| r.clone().with_bloom())
+            .collect::<Vec<_>>()
+            .encode(&mut encoded);
+        assert_eq!(decode_receipts(&encoded)?, receipts);
+
+        Ok(())
+    }
+}
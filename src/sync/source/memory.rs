@@ -0,0 +1,343 @@
+use std::{
+    collections::BTreeSet,
+    ops::{Range, RangeInclusive},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+
+use alloy_primitives::{Address, Bloom, Bytes, Log, B256};
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Result};
+use reth_primitives::{
+    Header, Receipt, Signature, Transaction, TransactionSigned, TransactionSignedNoHash, TxKind,
+    TxLegacy, TxType,
+};
+
+use super::{BlockSource, Pruned};
+
+/// Seconds between consecutive blocks
+pub const BLOCK_TIME: u64 = 12;
+
+/// An in-memory chain, used to test sync workers without a reth datadir
+/// Blocks are pushed explicitly, and the chain can be reorged at any point
+#[derive(Debug)]
+pub struct InMemorySource {
+    state: RwLock<State>,
+
+    /// Number of blocks whose receipts were read
+    pub receipt_reads: AtomicUsize,
+
+    /// Number of blocks whose transactions were read
+    pub tx_reads: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    blocks: Vec<FixtureBlock>,
+
+    /// incremented for each transaction, so that all hashes are unique
+    tx_nonce: u64,
+
+    /// incremented on each reorg, so that replacement blocks get different hashes
+    forks: u64,
+
+    /// Last block whose body and receipts are available, if not all of them
+    /// Later blocks only have a header, like those of a node that is still syncing
+    complete: Option<u64>,
+
+    /// Data dropped, like by a node with a prune config
+    pruned: Pruned,
+
+    /// How long reading a block's transactions takes, to simulate a slow node
+    read_delay: Duration,
+}
+
+#[derive(Debug)]
+struct FixtureBlock {
+    header: Header,
+    hash: B256,
+    txs: Vec<(TransactionSignedNoHash, FixtureTx)>,
+}
+
+/// A transaction to be included in a fixture block
+#[derive(Debug, Clone)]
+pub struct FixtureTx {
+    pub from: Address,
+    pub to: Option<Address>,
+    pub logs: Vec<Log>,
+
+    /// Contract created by this transaction, if any
+    pub creates: Option<Address>,
+}
+
+impl FixtureTx {
+    /// A plain transfer between two accounts
+    pub fn transfer(from: Address, to: Address) -> Self {
+        Self {
+            from,
+            to: Some(to),
+            logs: vec![],
+            creates: None,
+        }
+    }
+
+    /// A contract deployment
+    pub fn deploy(from: Address, contract: Address) -> Self {
+        Self {
+            from,
+            to: None,
+            logs: vec![],
+            creates: Some(contract),
+        }
+    }
+
+    /// A call to `contract`, emitting an ERC20-like `Transfer(from, to)` event
+    pub fn erc20_transfer(sender: Address, contract: Address, from: Address, to: Address) -> Self {
+        let topics = vec![B256::repeat_byte(0xdd), from.into_word(), to.into_word()];
+
+        Self {
+            from: sender,
+            to: Some(contract),
+            logs: vec![Log::new_unchecked(contract, topics, Bytes::new())],
+            creates: None,
+        }
+    }
+}
+
+impl Default for InMemorySource {
+    fn default() -> Self {
+        let source = Self {
+            state: Default::default(),
+            receipt_reads: Default::default(),
+            tx_reads: Default::default(),
+        };
+        source.push_block(vec![]);
+        source
+    }
+}
+
+impl InMemorySource {
+    /// Appends a new block on top of the current tip, returning its number
+    pub fn push_block(&self, txs: Vec<FixtureTx>) -> u64 {
+        let mut state = self.state.write().unwrap();
+
+        let mut logs_bloom = Bloom::default();
+        txs.iter()
+            .flat_map(|f| f.logs.iter())
+            .for_each(|log| logs_bloom.accrue_log(log));
+
+        let parent = state.blocks.last();
+        let number = parent.map_or(0, |b| b.header.number + 1);
+        let header = Header {
+            number,
+            timestamp: number * BLOCK_TIME,
+            parent_hash: parent.map_or(B256::ZERO, |b| b.hash),
+            nonce: state.forks,
+            logs_bloom,
+            ..Default::default()
+        };
+
+        let txs = txs
+            .into_iter()
+            .map(|fixture| {
+                state.tx_nonce += 1;
+                let tx = TransactionSignedNoHash {
+                    signature: Signature::default(),
+                    transaction: Transaction::Legacy(TxLegacy {
+                        nonce: state.tx_nonce,
+                        to: fixture.to.map_or(TxKind::Create, TxKind::Call),
+                        ..Default::default()
+                    }),
+                };
+                (tx, fixture)
+            })
+            .collect();
+
+        let number = header.number;
+        state.blocks.push(FixtureBlock {
+            hash: header.hash_slow(),
+            header,
+            txs,
+        });
+
+        number
+    }
+
+    /// Drops all blocks above `fork_block`
+    /// Blocks pushed afterwards form a new branch, with different hashes from the dropped ones
+    pub fn reorg(&self, fork_block: u64) {
+        let mut state = self.state.write().unwrap();
+        state.blocks.truncate(fork_block as usize + 1);
+        state.forks += 1;
+    }
+
+    /// Writes a range of blocks into an Era1 file
+    pub async fn write_era1(&self, path: &Path, blocks: RangeInclusive<u64>) -> Result<()> {
+        let mut contents = Vec::new();
+        for number in blocks {
+            let header = self
+                .header(number)
+                .await?
+                .ok_or_else(|| eyre!("block {} not found", number))?;
+            let transactions = self
+                .transactions(number)
+                .await?
+                .into_iter()
+                .map(|tx| tx.with_hash())
+                .collect();
+            let receipts = self.receipts(number).await?.into_iter().flatten().collect();
+
+            contents.push((header, transactions, receipts));
+        }
+
+        super::era1::write(path, contents)
+    }
+
+    /// Makes only blocks up to `complete` fully available, or all of them if `None`
+    pub fn set_complete(&self, complete: Option<u64>) {
+        self.state.write().unwrap().complete = complete;
+    }
+
+    /// Drops receipts up to `pruned.receipts`. Other segments are only reported as pruned
+    pub fn set_pruned(&self, pruned: Pruned) {
+        self.state.write().unwrap().pruned = pruned;
+    }
+
+    /// Makes each read of a block's transactions take `delay`
+    pub fn set_read_delay(&self, delay: Duration) {
+        self.state.write().unwrap().read_delay = delay;
+    }
+
+    /// Like `with_block`, but fails for blocks that only have a header
+    fn with_body<T>(&self, number: u64, f: impl FnOnce(&FixtureBlock) -> T) -> Result<T> {
+        if self
+            .state
+            .read()
+            .unwrap()
+            .complete
+            .is_some_and(|c| number > c)
+        {
+            return Err(eyre!("missing body of block {}", number));
+        }
+        self.with_block(number, f)
+    }
+
+    fn with_block<T>(&self, number: u64, f: impl FnOnce(&FixtureBlock) -> T) -> Result<T> {
+        let state = self.state.read().unwrap();
+        state
+            .blocks
+            .get(number as usize)
+            .map(f)
+            .ok_or_else(|| eyre!("block {} not found", number))
+    }
+}
+
+#[async_trait]
+impl BlockSource for InMemorySource {
+    async fn tip(&self) -> Result<u64> {
+        let state = self.state.read().unwrap();
+        let last = state.blocks.len() as u64 - 1;
+        Ok(state.complete.map_or(last, |complete| complete.min(last)))
+    }
+
+    async fn header(&self, number: u64) -> Result<Option<Header>> {
+        Ok(self.with_block(number, |b| b.header.clone()).ok())
+    }
+
+    async fn block_hash(&self, number: u64) -> Result<Option<B256>> {
+        Ok(self.with_block(number, |b| b.hash).ok())
+    }
+
+    async fn transactions(&self, number: u64) -> Result<Vec<TransactionSignedNoHash>> {
+        self.tx_reads.fetch_add(1, Ordering::SeqCst);
+        let delay = self.state.read().unwrap().read_delay;
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        self.with_body(number, |b| b.txs.iter().map(|(tx, _)| tx.clone()).collect())
+    }
+
+    async fn senders(&self, number: u64) -> Result<Vec<Option<Address>>> {
+        self.with_body(number, |b| {
+            b.txs.iter().map(|(_, f)| Some(f.from)).collect()
+        })
+    }
+
+    async fn receipts(&self, number: u64) -> Result<Vec<Option<Receipt>>> {
+        self.receipt_reads.fetch_add(1, Ordering::SeqCst);
+        let pruned = self.state.read().unwrap().pruned;
+        let available = pruned.receipts.map_or(true, |last| number > last);
+        self.with_body(number, |b| {
+            b.txs
+                .iter()
+                .map(|(_, f)| {
+                    available.then(|| Receipt {
+                        tx_type: TxType::Legacy,
+                        success: true,
+                        cumulative_gas_used: 0,
+                        logs: f.logs.clone(),
+                    })
+                })
+                .collect()
+        })
+    }
+
+    async fn pruned(&self) -> Result<Pruned> {
+        Ok(self.state.read().unwrap().pruned)
+    }
+
+    /// Fixture transactions don't carry values, so every sender and recipient counts as changed
+    async fn account_history(
+        &self,
+        addresses: &[Address],
+        range: Range<u64>,
+    ) -> Result<Option<BTreeSet<u64>>> {
+        let state = self.state.read().unwrap();
+        let involves = |f: &FixtureTx| {
+            addresses
+                .iter()
+                .any(|a| f.from == *a || f.to == Some(*a) || f.creates == Some(*a))
+        };
+
+        Ok(Some(
+            state
+                .blocks
+                .iter()
+                .filter(|b| range.contains(&b.header.number))
+                .filter(|b| b.txs.iter().any(|(_, f)| involves(f)))
+                .map(|b| b.header.number)
+                .collect(),
+        ))
+    }
+
+    async fn has_code(&self, address: Address, number: u64) -> Result<Option<bool>> {
+        let state = self.state.read().unwrap();
+        Ok(Some(
+            state
+                .blocks
+                .iter()
+                .take_while(|b| b.header.number <= number)
+                .flat_map(|b| b.txs.iter())
+                .any(|(_, f)| f.creates == Some(address)),
+        ))
+    }
+
+    async fn finality(&self, safe_depth: u64) -> Result<(u64, u64)> {
+        let tip = self.tip().await?;
+        Ok((tip.saturating_sub(safe_depth), 0))
+    }
+
+    async fn transaction_by_hash(&self, hash: B256) -> Result<Option<TransactionSigned>> {
+        let state = self.state.read().unwrap();
+        Ok(state
+            .blocks
+            .iter()
+            .flat_map(|b| b.txs.iter())
+            .find(|(tx, _)| tx.hash() == hash)
+            .map(|(tx, _)| tx.clone().with_hash()))
+    }
+}
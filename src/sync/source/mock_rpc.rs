@@ -0,0 +1,235 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use alloy_primitives::{Address, B256};
+use axum::{extract::State, routing::post, Json, Router};
+use color_eyre::eyre::{eyre, Result};
+use reth_primitives::TransactionSignedNoHash;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+use super::{memory::InMemorySource, BlockSource};
+
+/// A local JSON-RPC server exposing an `InMemorySource`, to test `RpcSource` against
+/// Only the handful of methods `RpcSource` relies on are supported
+#[derive(Debug)]
+pub struct MockRpc {
+    pub url: String,
+
+    /// Number of HTTP requests received (a batch counts as one)
+    pub requests: Arc<AtomicUsize>,
+}
+
+#[derive(Debug, Clone)]
+struct MockState {
+    source: Arc<InMemorySource>,
+    chain_id: u64,
+    requests: Arc<AtomicUsize>,
+}
+
+impl MockRpc {
+    pub async fn serve(source: Arc<InMemorySource>, chain_id: u64) -> Result<Self> {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let state = MockState {
+            source,
+            chain_id,
+            requests: requests.clone(),
+        };
+
+        let app = Router::new().route("/", post(rpc)).with_state(state);
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Ok(Self { url, requests })
+    }
+}
+
+async fn rpc(State(state): State<MockState>, Json(body): Json<Value>) -> Json<Value> {
+    state.requests.fetch_add(1, Ordering::SeqCst);
+
+    match body {
+        Value::Array(calls) => {
+            let mut responses = Vec::with_capacity(calls.len());
+            for call in calls {
+                responses.push(state.handle(call).await);
+            }
+            Json(Value::Array(responses))
+        }
+        call => Json(state.handle(call).await),
+    }
+}
+
+impl MockState {
+    async fn handle(&self, call: Value) -> Value {
+        let params = &call["params"];
+        let result = match call["method"].as_str().unwrap_or_default() {
+            "eth_chainId" => Ok(json!(quantity(self.chain_id))),
+            "eth_blockNumber" => self.source.tip().await.map(|tip| json!(quantity(tip))),
+            "eth_getBlockByNumber" => match self.block_number(&params[0]).await {
+                Ok(number) => {
+                    self.block(number, params[1].as_bool().unwrap_or(false))
+                        .await
+                }
+                Err(e) => Err(e),
+            },
+            "eth_getBlockReceipts" => match self.block_number(&params[0]).await {
+                Ok(number) => self.receipts(number).await,
+                Err(e) => Err(e),
+            },
+            "eth_getTransactionByHash" => match serde_json::from_value(params[0].clone()) {
+                Ok(hash) => self.transaction(hash).await,
+                Err(e) => Err(e.into()),
+            },
+            method => Err(eyre!("method {} not supported", method)),
+        };
+
+        match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": call["id"], "result": result }),
+            Err(e) => json!({
+                "jsonrpc": "2.0",
+                "id": call["id"],
+                "error": { "code": -32000, "message": e.to_string() }
+            }),
+        }
+    }
+
+    /// Parses a block number parameter. `safe` and `finalized` tags are not supported
+    async fn block_number(&self, param: &Value) -> Result<u64> {
+        match param.as_str() {
+            Some("latest") => self.source.tip().await,
+            Some(hex) if hex.starts_with("0x") => Ok(u64::from_str_radix(&hex[2..], 16)?),
+            _ => Err(eyre!("unsupported block number {}", param)),
+        }
+    }
+
+    async fn block(&self, number: u64, full: bool) -> Result<Value> {
+        let Some(header) = self.source.header(number).await? else {
+            return Ok(Value::Null);
+        };
+        let hash = header.hash_slow();
+        let txs = self.source.transactions(number).await?;
+        let senders = self.source.senders(number).await?;
+
+        let transactions: Vec<_> = txs
+            .iter()
+            .zip(senders)
+            .enumerate()
+            .map(|(index, (tx, from))| match full {
+                true => transaction(tx, from.unwrap_or_default(), hash, number, index),
+                false => json!(tx.hash()),
+            })
+            .collect();
+
+        Ok(json!({
+            "hash": hash,
+            "parentHash": header.parent_hash,
+            "sha3Uncles": header.ommers_hash,
+            "miner": header.beneficiary,
+            "stateRoot": header.state_root,
+            "transactionsRoot": header.transactions_root,
+            "receiptsRoot": header.receipts_root,
+            "logsBloom": header.logs_bloom,
+            "difficulty": quantity(header.difficulty),
+            "number": quantity(header.number),
+            "gasLimit": quantity(header.gas_limit),
+            "gasUsed": quantity(header.gas_used),
+            "timestamp": quantity(header.timestamp),
+            "extraData": header.extra_data,
+            "mixHash": header.mix_hash,
+            "nonce": format!("{:#018x}", header.nonce),
+            "totalDifficulty": "0x0",
+            "size": "0x0",
+            "uncles": [],
+            "transactions": transactions,
+        }))
+    }
+
+    async fn receipts(&self, number: u64) -> Result<Value> {
+        if self.source.header(number).await?.is_none() {
+            return Ok(Value::Null);
+        }
+
+        let receipts: Vec<_> = self
+            .source
+            .receipts(number)
+            .await?
+            .into_iter()
+            .flatten()
+            .map(|receipt| {
+                let logs: Vec<_> = receipt
+                    .logs
+                    .iter()
+                    .map(|log| {
+                        json!({
+                            "address": log.address,
+                            "topics": log.topics(),
+                            "data": log.data.data,
+                        })
+                    })
+                    .collect();
+
+                json!({
+                    "type": quantity(u8::from(receipt.tx_type)),
+                    "status": quantity(receipt.success as u8),
+                    "cumulativeGasUsed": quantity(receipt.cumulative_gas_used),
+                    "logs": logs,
+                })
+            })
+            .collect();
+
+        Ok(json!(receipts))
+    }
+
+    async fn transaction(&self, hash: B256) -> Result<Value> {
+        for number in 0..=self.source.tip().await? {
+            let txs = self.source.transactions(number).await?;
+            let Some(index) = txs.iter().position(|tx| tx.hash() == hash) else {
+                continue;
+            };
+
+            let senders = self.source.senders(number).await?;
+            let block_hash = self.source.block_hash(number).await?.unwrap_or_default();
+            let from = senders[index].unwrap_or_default();
+
+            return Ok(transaction(&txs[index], from, block_hash, number, index));
+        }
+
+        Ok(Value::Null)
+    }
+}
+
+/// Renders a legacy transaction as returned by `eth_getTransactionByHash`
+fn transaction(
+    tx: &TransactionSignedNoHash,
+    from: Address,
+    block_hash: B256,
+    block_number: u64,
+    index: usize,
+) -> Value {
+    let signature = &tx.signature;
+
+    json!({
+        "hash": tx.hash(),
+        "blockHash": block_hash,
+        "blockNumber": quantity(block_number),
+        "transactionIndex": quantity(index),
+        "type": "0x0",
+        "from": from,
+        "to": tx.transaction.to(),
+        "nonce": quantity(tx.transaction.nonce()),
+        "value": quantity(tx.transaction.value()),
+        "gas": quantity(tx.transaction.gas_limit()),
+        "gasPrice": quantity(tx.transaction.max_fee_per_gas()),
+        "input": tx.transaction.input(),
+        "v": quantity(27 + signature.odd_y_parity as u8),
+        "r": quantity(signature.r),
+        "s": quantity(signature.s),
+    })
+}
+
+fn quantity(n: impl std::fmt::LowerHex) -> String {
+    format!("{:#x}", n)
+}
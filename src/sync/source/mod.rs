@@ -0,0 +1,282 @@
+mod era1;
+#[cfg(test)]
+pub mod memory;
+#[cfg(test)]
+pub mod mock_rpc;
+#[cfg(feature = "exex")]
+mod provider;
+mod reth;
+mod rpc;
+
+use std::{collections::BTreeSet, ops::Range, sync::Arc};
+
+use alloy_primitives::{Address, B256};
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Result};
+use reth_primitives::{Header, Receipt, TransactionSigned, TransactionSignedNoHash};
+
+#[cfg(feature = "exex")]
+pub use self::provider::ProviderSource;
+pub use self::{era1::Era1Source, reth::RethProviderFactory, rpc::RpcSource};
+use crate::config::ChainConfig;
+
+/// A source of block data for the sync workers
+///
+/// Methods are keyed by block number, and per-transaction data is returned in the same order as
+/// the block's transactions, so that each entry of `transactions`, `senders` and `receipts`
+/// refers to the same transaction
+#[async_trait]
+pub trait BlockSource: std::fmt::Debug + Send + Sync {
+    /// Number of the latest block whose transactions, senders and receipts are all available
+    /// Headers of later blocks may already be, e.g. while the node is still syncing
+    async fn tip(&self) -> Result<u64>;
+
+    /// Header of a canonical block, if it exists yet
+    async fn header(&self, number: u64) -> Result<Option<Header>>;
+
+    /// Hash of a canonical block, if it exists yet
+    async fn block_hash(&self, number: u64) -> Result<Option<B256>>;
+
+    /// Transactions included in a block
+    async fn transactions(&self, number: u64) -> Result<Vec<TransactionSignedNoHash>>;
+
+    /// Sender of each transaction in a block, if it can be determined
+    async fn senders(&self, number: u64) -> Result<Vec<Option<Address>>>;
+
+    /// Receipt of each transaction in a block, if available
+    async fn receipts(&self, number: u64) -> Result<Vec<Option<Receipt>>>;
+
+    /// Reads a range of blocks at once, in ascending order
+    /// Receipts are only read for blocks whose header passes `with_receipts`
+    ///
+    /// The default implementation reads each block separately. Sources that can walk
+    /// consecutive blocks with sequential reads should override it
+    async fn blocks(
+        &self,
+        range: Range<u64>,
+        with_receipts: &(dyn Fn(&Header) -> bool + Sync),
+    ) -> Result<Vec<BlockData>> {
+        read_blocks(self, range, with_receipts).await
+    }
+
+    /// Headers of a range of blocks, in ascending order
+    async fn headers(&self, range: Range<u64>) -> Result<Vec<Header>> {
+        read_headers(self, range).await
+    }
+
+    /// Blocks within `range` in which the nonce or balance of any of `addresses` changed,
+    /// according to the node's account history index
+    /// `None` if no such index covers the range, in which case every block has to be searched
+    async fn account_history(
+        &self,
+        _addresses: &[Address],
+        _range: Range<u64>,
+    ) -> Result<Option<BTreeSet<u64>>> {
+        Ok(None)
+    }
+
+    /// Whether an account had code at the end of a block
+    /// `None` if the source can't tell, e.g. because it keeps no historical state
+    async fn has_code(&self, _address: Address, _number: u64) -> Result<Option<bool>> {
+        Ok(None)
+    }
+
+    /// Blocks whose data the node has pruned
+    async fn pruned(&self) -> Result<Pruned> {
+        Ok(Pruned::default())
+    }
+
+    /// Current `(safe, finalized)` block numbers
+    async fn finality(&self, safe_depth: u64) -> Result<(u64, u64)>;
+
+    /// Looks up a transaction by its hash
+    async fn transaction_by_hash(&self, hash: B256) -> Result<Option<TransactionSigned>>;
+}
+
+/// Data a node has pruned, as the last block of each pruned segment
+/// Nodes always prune from genesis up to some block, never in between
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Pruned {
+    /// Receipts, without which matches by log topic can't be found
+    pub receipts: Option<u64>,
+
+    /// Transaction senders. These are recovered from signatures instead, at a cost
+    pub senders: Option<u64>,
+
+    /// Account history index, without which `BackfillMethod::HistoryIndex` searches every block
+    pub account_history: Option<u64>,
+}
+
+impl Pruned {
+    /// First block from which every match can be found
+    pub fn complete_from(&self) -> u64 {
+        self.receipts.map_or(0, |last| last + 1)
+    }
+}
+
+/// All the data needed to match a block's transactions
+#[derive(Debug)]
+pub struct BlockData {
+    pub header: Header,
+    pub transactions: Vec<TransactionSignedNoHash>,
+    pub senders: Vec<Option<Address>>,
+
+    /// `None` if receipts weren't read for this block
+    pub receipts: Option<Vec<Option<Receipt>>>,
+}
+
+/// Reads a range of blocks one at a time, using the per-block methods of a source
+async fn read_blocks<S: BlockSource + ?Sized>(
+    source: &S,
+    range: Range<u64>,
+    with_receipts: &(dyn Fn(&Header) -> bool + Sync),
+) -> Result<Vec<BlockData>> {
+    let mut blocks = Vec::with_capacity(range.clone().count());
+
+    for number in range {
+        let header = source
+            .header(number)
+            .await?
+            .ok_or_else(|| eyre!("missing header for block {}", number))?;
+        let receipts = match with_receipts(&header) {
+            true => Some(source.receipts(number).await?),
+            false => None,
+        };
+
+        blocks.push(BlockData {
+            transactions: source.transactions(number).await?,
+            senders: source.senders(number).await?,
+            receipts,
+            header,
+        });
+    }
+
+    Ok(blocks)
+}
+
+/// Reads a range of headers one at a time
+async fn read_headers<S: BlockSource + ?Sized>(
+    source: &S,
+    range: Range<u64>,
+) -> Result<Vec<Header>> {
+    let mut headers = Vec::with_capacity(range.clone().count());
+
+    for number in range {
+        headers.push(
+            source
+                .header(number)
+                .await?
+                .ok_or_else(|| eyre!("missing header for block {}", number))?,
+        );
+    }
+
+    Ok(headers)
+}
+
+/// Reads only the blocks of a range that may have a match, skipping the rest after their header
+/// A block may match if it is one of the `candidates` given by the account history index, or if
+/// its header passes `with_receipts`, in which case its receipts are read as well
+pub async fn read_candidates<S: BlockSource + ?Sized>(
+    source: &S,
+    range: Range<u64>,
+    candidates: &BTreeSet<u64>,
+    with_receipts: &(dyn Fn(&Header) -> bool + Sync),
+) -> Result<Vec<BlockData>> {
+    let mut blocks = Vec::new();
+
+    for header in source.headers(range).await? {
+        let number = header.number;
+        let receipts = match with_receipts(&header) {
+            true => Some(source.receipts(number).await?),
+            false if candidates.contains(&number) => None,
+            false => continue,
+        };
+
+        blocks.push(BlockData {
+            transactions: source.transactions(number).await?,
+            senders: source.senders(number).await?,
+            receipts,
+            header,
+        });
+    }
+
+    Ok(blocks)
+}
+
+/// Finds the block in which a contract was deployed, by binary-searching for the first block
+/// within `range` at whose end the address has code
+/// `None` if it has no code at the end of the range (e.g.: it is an EOA), or if the source can't
+/// tell. Assumes the code, once deployed, is never removed
+pub async fn deployment_block<S: BlockSource + ?Sized>(
+    source: &S,
+    address: Address,
+    range: Range<u64>,
+) -> Result<Option<u64>> {
+    let Some(last) = range.end.checked_sub(1).filter(|last| *last >= range.start) else {
+        return Ok(None);
+    };
+    if source.has_code(address, last).await? != Some(true) {
+        return Ok(None);
+    }
+
+    // invariant: the address has code at the end of `high`
+    let (mut low, mut high) = (range.start, last);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match source.has_code(address, mid).await? {
+            Some(true) => high = mid,
+            Some(false) => low = mid + 1,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(high))
+}
+
+/// Finds the first block within `range` with a timestamp at or after `timestamp`, by
+/// binary-searching over header timestamps
+/// `range.end` if every block in the range is older
+pub async fn block_at_timestamp<S: BlockSource + ?Sized>(
+    source: &S,
+    timestamp: u64,
+    range: Range<u64>,
+) -> Result<u64> {
+    let (mut low, mut high) = (range.start, range.end);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let header = source
+            .header(mid)
+            .await?
+            .ok_or_else(|| eyre!("block {} not found", mid))?;
+        match header.timestamp >= timestamp {
+            true => high = mid,
+            false => low = mid + 1,
+        }
+    }
+
+    Ok(low)
+}
+
+/// Builds the block source configured for a chain
+pub async fn block_source(chain: &ChainConfig) -> Result<Arc<dyn BlockSource>> {
+    match (&chain.reth, &chain.rpc) {
+        (Some(_), None) => Ok(Arc::new(RethProviderFactory::new(chain)?)),
+        (None, Some(_)) => Ok(Arc::new(RpcSource::new(chain).await?)),
+        _ => Err(eyre!(
+            "chain {} must configure exactly one of `reth` or `rpc`",
+            chain.chain_id
+        )),
+    }
+}
+
+/// Builds the block source used by backfill workers
+/// Blocks covered by local Era1 archives are read from them, instead of the chain's main source
+pub fn backfill_source(
+    chain: &ChainConfig,
+    source: Arc<dyn BlockSource>,
+) -> Result<Arc<dyn BlockSource>> {
+    match &chain.era1 {
+        Some(dir) => Ok(Arc::new(Era1Source::new(dir, source)?)),
+        None => Ok(source),
+    }
+}
@@ -0,0 +1,100 @@
+use std::ops::Range;
+
+use alloy_primitives::{Address, B256};
+use async_trait::async_trait;
+use color_eyre::eyre::Result;
+use reth_primitives::{Header, Receipt, TransactionSigned, TransactionSignedNoHash};
+use reth_provider::{BlockIdReader, BlockReader, StateProvider, StateProviderFactory};
+
+use super::{
+    reth::{blocks, headers, receipts, senders, transactions, tx_range},
+    BlockData, BlockSource,
+};
+
+/// Reads block data through a running node's provider, when running as a reth Execution Extension
+/// Unlike `RethProviderFactory`, this also sees canonical blocks not yet persisted to disk, and the
+/// node's actual safe block
+pub struct ProviderSource<P> {
+    provider: P,
+}
+
+impl<P> ProviderSource<P> {
+    pub fn new(provider: P) -> Self {
+        Self { provider }
+    }
+}
+
+impl<P> std::fmt::Debug for ProviderSource<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProviderSource").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<P> BlockSource for ProviderSource<P>
+where
+    P: BlockReader + BlockIdReader + StateProviderFactory + Send + Sync + 'static,
+{
+    async fn tip(&self) -> Result<u64> {
+        Ok(self.provider.best_block_number()?)
+    }
+
+    async fn header(&self, number: u64) -> Result<Option<Header>> {
+        Ok(self.provider.header_by_number(number)?)
+    }
+
+    async fn block_hash(&self, number: u64) -> Result<Option<B256>> {
+        Ok(self.provider.block_hash(number)?)
+    }
+
+    async fn transactions(&self, number: u64) -> Result<Vec<TransactionSignedNoHash>> {
+        transactions(&self.provider, tx_range(&self.provider, number)?)
+    }
+
+    async fn senders(&self, number: u64) -> Result<Vec<Option<Address>>> {
+        senders(&self.provider, number)
+    }
+
+    async fn receipts(&self, number: u64) -> Result<Vec<Option<Receipt>>> {
+        receipts(&self.provider, tx_range(&self.provider, number)?)
+    }
+
+    async fn blocks(
+        &self,
+        range: Range<u64>,
+        with_receipts: &(dyn Fn(&Header) -> bool + Sync),
+    ) -> Result<Vec<BlockData>> {
+        blocks(&self.provider, range, with_receipts)
+    }
+
+    async fn headers(&self, range: Range<u64>) -> Result<Vec<Header>> {
+        headers(&self.provider, range)
+    }
+
+    async fn has_code(&self, address: Address, number: u64) -> Result<Option<bool>> {
+        let state = self.provider.history_by_block_number(number)?;
+        Ok(Some(
+            state
+                .account_code(address)?
+                .is_some_and(|code| !code.is_empty()),
+        ))
+    }
+
+    /// Falls back to `safe_depth` blocks behind the tip if the node doesn't know a safe block yet
+    async fn finality(&self, safe_depth: u64) -> Result<(u64, u64)> {
+        let finalized = self.provider.finalized_block_number()?.unwrap_or(0);
+        let safe = match self.provider.safe_block_number()? {
+            Some(safe) => safe,
+            None => self
+                .provider
+                .best_block_number()?
+                .saturating_sub(safe_depth),
+        };
+
+        Ok((safe.max(finalized), finalized))
+    }
+
+    async fn transaction_by_hash(&self, hash: B256) -> Result<Option<TransactionSigned>> {
+        Ok(self.provider.transaction_by_hash(hash)?)
+    }
+}
@@ -0,0 +1,510 @@
+use std::{
+    collections::BTreeSet,
+    ops::Range,
+    os::unix::fs::MetadataExt,
+    sync::{Arc, Mutex, RwLock},
+    time::SystemTime,
+};
+
+use alloy_genesis::Genesis;
+use alloy_primitives::{Address, B256};
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Result};
+use reth_chainspec::ChainSpec;
+use reth_db::{
+    cursor::DbCursorRO,
+    mdbx::{tx::Tx, RO},
+    models::ShardedKey,
+    open_db_read_only, tables,
+    transaction::DbTx,
+    DatabaseEnv,
+};
+use reth_primitives::{Header, Receipt, TransactionSigned, TransactionSignedNoHash};
+use reth_provider::{
+    providers::StaticFileProvider, BlockHashReader, BlockNumReader, BlockReader, DatabaseProvider,
+    FinalizedBlockReader, HeaderProvider, ProviderFactory, PruneCheckpointReader, ReceiptProvider,
+    StageCheckpointReader, StateProvider, TransactionsProvider,
+};
+use reth_prune_types::PruneSegment;
+use reth_stages_types::StageId;
+use tracing::{info, warn};
+
+use super::{BlockData, BlockSource, Pruned};
+use crate::config::{ChainConfig, RethConfig};
+
+/// Wraps a provider to access Reth DB
+/// This is the production `BlockSource`, reading directly from reth's database files
+#[derive(Debug)]
+pub struct RethProviderFactory {
+    config: RethConfig,
+    spec: Arc<ChainSpec>,
+
+    /// Reth Provider factory
+    /// Replaced whenever reth's files change in a way an open read-only environment misses
+    factory: RwLock<ProviderFactory<DatabaseEnv>>,
+
+    /// Files `factory` was opened from
+    files: Mutex<Files>,
+}
+
+/// Identifies the files a provider factory was opened from
+/// reth replaces the MDBX file when it restarts from scratch, and adds a static file segment
+/// every so many blocks
+#[derive(Debug, PartialEq, Eq)]
+struct Files {
+    /// Device and inode of the MDBX data file
+    db: Option<(u64, u64)>,
+
+    /// Last modification of the static files directory, which changes as segments are added
+    static_files: Option<SystemTime>,
+}
+
+impl Files {
+    fn read(config: &RethConfig) -> Self {
+        Self {
+            db: std::fs::metadata(config.db.join("mdbx.dat"))
+                .ok()
+                .map(|m| (m.dev(), m.ino())),
+            static_files: std::fs::metadata(&config.static_files)
+                .and_then(|m| m.modified())
+                .ok(),
+        }
+    }
+}
+
+impl RethProviderFactory {
+    /// Creates a new Reth DB provider
+    /// Fails if the DB doesn't hold the genesis block of the configured chain
+    pub fn new(chain: &ChainConfig) -> Result<Self> {
+        let config = chain
+            .reth
+            .clone()
+            .ok_or_else(|| eyre!("no reth config for chain {}", chain.chain_id))?;
+        let spec = chain_spec(chain)?;
+
+        let files = Files::read(&config);
+        let factory = open(&config, spec.clone())?;
+
+        Ok(Self {
+            config,
+            spec,
+            factory: RwLock::new(factory),
+            files: Mutex::new(files),
+        })
+    }
+
+    pub fn get(&self) -> Result<DatabaseProvider<Tx<RO>>> {
+        Ok(self.factory.read().unwrap().provider()?)
+    }
+
+    /// Reopens reth's database and static files if they changed since they were last opened
+    /// If they can't be opened yet (e.g.: reth is still creating them), the current ones are kept,
+    /// and it is retried on the next call
+    fn refresh(&self) {
+        let mut files = self.files.lock().unwrap();
+        let current = Files::read(&self.config);
+        if *files == current {
+            return;
+        }
+
+        match open(&self.config, self.spec.clone()) {
+            Ok(factory) => {
+                info!(event = "reopen", db = ?self.config.db);
+                *self.factory.write().unwrap() = factory;
+                *files = current;
+            }
+            Err(err) => warn!(event = "reopen", error = %err),
+        }
+    }
+}
+
+/// Opens reth's database and static files, read-only
+/// Fails if the DB doesn't hold the genesis block of the given chain
+fn open(config: &RethConfig, spec: Arc<ChainSpec>) -> Result<ProviderFactory<DatabaseEnv>> {
+    let db = open_db_read_only(&config.db, Default::default())?;
+    let expected_genesis = spec.genesis_hash();
+    let chain_id = spec.chain.id();
+
+    let static_file_provider = StaticFileProvider::read_only(config.static_files.clone())?;
+
+    let factory: ProviderFactory<reth_db::DatabaseEnv> =
+        ProviderFactory::new(db, spec, static_file_provider);
+
+    match factory.block_hash(0)? {
+        Some(genesis) if genesis == expected_genesis => Ok(factory),
+        Some(genesis) => Err(eyre!(
+            "genesis mismatch for chain {}: reth DB has {}, expected {}",
+            chain_id,
+            genesis,
+            expected_genesis
+        )),
+        None => Err(eyre!("reth DB for chain {} has no genesis block", chain_id)),
+    }
+}
+
+#[async_trait]
+impl BlockSource for RethProviderFactory {
+    /// While reth syncs, headers are written well before the other stages get to the same block
+    /// Only blocks that went through every stage, as recorded by the `Finish` stage checkpoint,
+    /// are complete
+    async fn tip(&self) -> Result<u64> {
+        self.refresh();
+        synced_tip(&self.get()?)
+    }
+
+    async fn header(&self, number: u64) -> Result<Option<Header>> {
+        Ok(self.get()?.header_by_number(number)?)
+    }
+
+    async fn block_hash(&self, number: u64) -> Result<Option<B256>> {
+        Ok(self.get()?.block_hash(number)?)
+    }
+
+    async fn transactions(&self, number: u64) -> Result<Vec<TransactionSignedNoHash>> {
+        let provider = self.get()?;
+        transactions(&provider, tx_range(&provider, number)?)
+    }
+
+    async fn senders(&self, number: u64) -> Result<Vec<Option<Address>>> {
+        senders(&self.get()?, number)
+    }
+
+    async fn receipts(&self, number: u64) -> Result<Vec<Option<Receipt>>> {
+        let provider = self.get()?;
+        receipts(&provider, tx_range(&provider, number)?)
+    }
+
+    async fn blocks(
+        &self,
+        range: Range<u64>,
+        with_receipts: &(dyn Fn(&Header) -> bool + Sync),
+    ) -> Result<Vec<BlockData>> {
+        blocks(&self.get()?, range, with_receipts)
+    }
+
+    async fn headers(&self, range: Range<u64>) -> Result<Vec<Header>> {
+        headers(&self.get()?, range)
+    }
+
+    async fn account_history(
+        &self,
+        addresses: &[Address],
+        range: Range<u64>,
+    ) -> Result<Option<BTreeSet<u64>>> {
+        let provider = self.get()?;
+        if pruned(&provider)?
+            .account_history
+            .is_some_and(|last| range.start <= last)
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(account_history(&provider, addresses, range)?))
+    }
+
+    async fn has_code(&self, address: Address, number: u64) -> Result<Option<bool>> {
+        let state = self
+            .factory
+            .read()
+            .unwrap()
+            .history_by_block_number(number)?;
+        Ok(Some(
+            state
+                .account_code(address)?
+                .is_some_and(|code| !code.is_empty()),
+        ))
+    }
+
+    async fn pruned(&self) -> Result<Pruned> {
+        pruned(&self.get()?)
+    }
+
+    /// reth only persists the finalized block. The safe block is approximated as `safe_depth`
+    /// blocks behind the tip, but never behind the finalized one
+    async fn finality(&self, safe_depth: u64) -> Result<(u64, u64)> {
+        let provider = self.get()?;
+        let finalized = provider.last_finalized_block_number()?;
+        let tip = synced_tip(&provider)?;
+
+        Ok((tip.saturating_sub(safe_depth).max(finalized), finalized))
+    }
+
+    async fn transaction_by_hash(&self, hash: B256) -> Result<Option<TransactionSigned>> {
+        Ok(self.get()?.transaction_by_hash(hash)?)
+    }
+}
+
+/// Latest block that went through every stage of reth's pipeline
+pub(super) fn synced_tip(provider: &(impl BlockNumReader + StageCheckpointReader)) -> Result<u64> {
+    let finished = provider
+        .get_stage_checkpoint(StageId::Finish)?
+        .map(|checkpoint| checkpoint.block_number)
+        .unwrap_or_default();
+
+    Ok(finished.min(provider.last_block_number()?))
+}
+
+/// Blocks pruned by reth, according to its prune checkpoints
+/// Receipts pruned by the `ContractLogs` segment only keep those of a few configured contracts,
+/// so they count as pruned too
+pub(super) fn pruned(provider: &impl PruneCheckpointReader) -> Result<Pruned> {
+    let last = |segment| -> Result<Option<u64>> {
+        Ok(provider
+            .get_prune_checkpoint(segment)?
+            .and_then(|checkpoint| checkpoint.block_number))
+    };
+
+    Ok(Pruned {
+        receipts: last(PruneSegment::Receipts)?.max(last(PruneSegment::ContractLogs)?),
+        senders: last(PruneSegment::SenderRecovery)?,
+        account_history: last(PruneSegment::AccountHistory)?,
+    })
+}
+
+/// Range of transaction numbers included in a block
+pub(super) fn tx_range(provider: &impl BlockReader, number: u64) -> Result<Range<u64>> {
+    match provider.block_body_indices(number)? {
+        Some(indices) => Ok(indices.tx_num_range()),
+        None => Err(eyre!("missing body indices for block {}", number)),
+    }
+}
+
+/// Transactions in a range of transaction numbers, read with a single cursor walk
+pub(super) fn transactions(
+    provider: &impl BlockReader,
+    range: Range<u64>,
+) -> Result<Vec<TransactionSignedNoHash>> {
+    let txs = provider.transactions_by_tx_range(range.clone())?;
+
+    match txs.len() as u64 == range.end - range.start {
+        true => Ok(txs),
+        false => Err(eyre!("missing transactions in range {:?}", range)),
+    }
+}
+
+/// Receipts in a range of transaction numbers, read with a single cursor walk
+/// If some were pruned, each one is looked up individually instead, so that they stay aligned
+/// with their transactions
+pub(super) fn receipts(
+    provider: &impl BlockReader,
+    range: Range<u64>,
+) -> Result<Vec<Option<Receipt>>> {
+    let receipts = provider.receipts_by_tx_range(range.clone())?;
+
+    if receipts.len() as u64 == range.end - range.start {
+        return Ok(receipts.into_iter().map(Some).collect());
+    }
+
+    range.map(|id| Ok(provider.receipt(id)?)).collect()
+}
+
+/// Senders of a block's transactions, read in bulk from the `TransactionSenders` table
+/// Falls back to recovering them from signatures if the table was pruned for this block
+pub(super) fn senders(provider: &impl BlockReader, number: u64) -> Result<Vec<Option<Address>>> {
+    let range = tx_range(provider, number)?;
+    let senders = provider.senders_by_tx_range(range.clone())?;
+
+    if senders.len() as u64 == range.end - range.start {
+        return Ok(senders.into_iter().map(Some).collect());
+    }
+
+    Ok(transactions(provider, range)?
+        .iter()
+        .map(|tx| tx.recover_signer())
+        .collect())
+}
+
+/// Headers of a range of blocks, read with a single cursor walk
+pub(super) fn headers(provider: &impl HeaderProvider, range: Range<u64>) -> Result<Vec<Header>> {
+    let headers = provider.headers_range(range.clone())?;
+
+    match headers.len() as u64 == range.end - range.start {
+        true => Ok(headers),
+        false => Err(eyre!("missing headers in range {:?}", range)),
+    }
+}
+
+/// Blocks within `range` that changed the nonce or balance of any of the given accounts
+/// Read from the `AccountsHistory` index, which keeps, for each account, shards of the block
+/// numbers in which it appears in `AccountChangeSets`. Each shard is keyed by its highest block, so
+/// seeking to `range.start` lands on the first shard that may be relevant
+pub(super) fn account_history<TX: DbTx>(
+    provider: &DatabaseProvider<TX>,
+    addresses: &[Address],
+    range: Range<u64>,
+) -> Result<BTreeSet<u64>> {
+    let mut cursor = provider.tx_ref().cursor_read::<tables::AccountsHistory>()?;
+    let mut blocks = BTreeSet::new();
+
+    for address in addresses {
+        for entry in cursor.walk(Some(ShardedKey::new(*address, range.start)))? {
+            let (key, shard) = entry?;
+            if key.key != *address {
+                break;
+            }
+
+            blocks.extend(shard.iter().filter(|block| range.contains(block)));
+            if key.highest_block_number >= range.end {
+                break;
+            }
+        }
+    }
+
+    Ok(blocks)
+}
+
+/// Reads a range of blocks within a single read transaction
+/// Transactions and senders of the whole range are read with one cursor walk each. Receipts are
+/// read with one cursor walk per block that needs them
+pub(super) fn blocks(
+    provider: &impl BlockReader,
+    range: Range<u64>,
+    with_receipts: &(dyn Fn(&Header) -> bool + Sync),
+) -> Result<Vec<BlockData>> {
+    let headers = headers(provider, range.clone())?;
+
+    let tx_ranges = range
+        .map(|number| tx_range(provider, number))
+        .collect::<Result<Vec<_>>>()?;
+    let all_txs = match (tx_ranges.first(), tx_ranges.last()) {
+        (Some(first), Some(last)) => first.start..last.end,
+        _ => return Ok(vec![]),
+    };
+
+    let txs = transactions(provider, all_txs.clone())?;
+    let senders = provider.senders_by_tx_range(all_txs)?;
+    let senders: Vec<_> = match senders.len() == txs.len() {
+        true => senders.into_iter().map(Some).collect(),
+        false => txs.iter().map(|tx| tx.recover_signer()).collect(),
+    };
+
+    let mut txs = txs.into_iter();
+    let mut senders = senders.into_iter();
+    headers
+        .into_iter()
+        .zip(tx_ranges)
+        .map(|(header, block_txs)| {
+            let count = (block_txs.end - block_txs.start) as usize;
+            let receipts = match with_receipts(&header) {
+                true => Some(receipts(provider, block_txs)?),
+                false => None,
+            };
+
+            Ok(BlockData {
+                header,
+                transactions: txs.by_ref().take(count).collect(),
+                senders: senders.by_ref().take(count).collect(),
+                receipts,
+            })
+        })
+        .collect()
+}
+
+/// Resolves the chain spec for a chain, in order of preference:
+///   - from a genesis file
+///   - from a built-in spec, by name
+///   - from a built-in spec, by chain ID
+fn chain_spec(chain: &ChainConfig) -> Result<Arc<ChainSpec>> {
+    let spec = match (&chain.genesis, &chain.spec) {
+        (Some(path), _) => {
+            let genesis: Genesis = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            Arc::new(ChainSpec::from(genesis))
+        }
+        (None, Some(name)) => match name.as_str() {
+            "mainnet" => reth_chainspec::MAINNET.clone(),
+            "sepolia" => reth_chainspec::SEPOLIA.clone(),
+            "holesky" => reth_chainspec::HOLESKY.clone(),
+            "dev" => reth_chainspec::DEV.clone(),
+            _ => return Err(eyre!("unknown chain spec {}", name)),
+        },
+        (None, None) => match chain.chain_id {
+            1 => reth_chainspec::MAINNET.clone(),
+            11155111 => reth_chainspec::SEPOLIA.clone(),
+            17000 => reth_chainspec::HOLESKY.clone(),
+            id => {
+                return Err(eyre!(
+                    "no built-in spec for chain id {}. Set `spec` or `genesis` instead",
+                    id
+                ))
+            }
+        },
+    };
+
+    if spec.chain.id() != chain.chain_id as u64 {
+        return Err(eyre!(
+            "chain spec is for chain id {}, but {} was configured",
+            spec.chain.id(),
+            chain.chain_id
+        ));
+    }
+
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+    use crate::config::Config;
+
+    fn chain_config(chain_id: i32) -> ChainConfig {
+        ChainConfig {
+            chain_id,
+            ..Config::for_test().chains[0].clone()
+        }
+    }
+
+    #[test]
+    fn test_spec_from_chain_id() -> Result<()> {
+        assert_eq!(chain_spec(&chain_config(1))?.chain.id(), 1);
+        assert_eq!(chain_spec(&chain_config(17000))?.chain.id(), 17000);
+        assert!(chain_spec(&chain_config(12345)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spec_from_name() -> Result<()> {
+        let config = ChainConfig {
+            spec: Some("dev".to_owned()),
+            ..chain_config(1337)
+        };
+        assert_eq!(
+            chain_spec(&config)?.genesis_hash(),
+            reth_chainspec::DEV.genesis_hash()
+        );
+
+        let mismatch = ChainConfig {
+            spec: Some("holesky".to_owned()),
+            ..chain_config(1)
+        };
+        assert!(chain_spec(&mismatch).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spec_from_genesis_file() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        write!(
+            file,
+            r#"{{
+                "config": {{ "chainId": 4242, "homesteadBlock": 0, "eip150Block": 0 }},
+                "nonce": "0x0",
+                "timestamp": "0x0",
+                "extraData": "0x",
+                "gasLimit": "0x1c9c380",
+                "difficulty": "0x1",
+                "alloc": {{}}
+            }}"#
+        )?;
+
+        let config = ChainConfig {
+            genesis: Some(file.path().to_path_buf()),
+            ..chain_config(4242)
+        };
+        assert_eq!(chain_spec(&config)?.chain.id(), 4242);
+        Ok(())
+    }
+}
@@ -0,0 +1,588 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use alloy_primitives::{Address, Bytes, Log, B256, U64, U8};
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Result};
+use reth_primitives::{Block, Header, Receipt, TransactionSigned, TransactionSignedNoHash, TxType};
+use reth_rpc_types::BlockTransactions;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+use super::BlockSource;
+use crate::config::ChainConfig;
+
+/// Reads block data from a node over HTTP JSON-RPC
+/// Meant for nodes whose database we can't read directly, such as a remote node, or anvil/hardhat
+/// during local development
+///
+/// Blocks and their receipts are fetched in batches, and cached, since each block is read several
+/// times while being processed
+#[derive(Debug)]
+pub struct RpcSource {
+    client: reqwest::Client,
+    url: String,
+
+    /// How many blocks to fetch per batch request
+    batch_size: u64,
+
+    /// Latest tip seen, so that we don't prefetch blocks that don't exist yet
+    tip: AtomicU64,
+
+    cache: Mutex<Cache>,
+}
+
+/// Recently fetched blocks, evicted in insertion order
+#[derive(Debug)]
+struct Cache {
+    blocks: HashMap<u64, Arc<RpcBlock>>,
+    order: VecDeque<u64>,
+    capacity: usize,
+}
+
+#[derive(Debug)]
+struct RpcBlock {
+    hash: B256,
+    header: Header,
+    transactions: Vec<TransactionSignedNoHash>,
+    senders: Vec<Address>,
+    receipts: Vec<Receipt>,
+}
+
+/// A JSON-RPC response. A `null` or missing result is mapped to `None`
+#[derive(Debug, Deserialize)]
+struct Response {
+    id: usize,
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<ResponseError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseError {
+    code: i64,
+    message: String,
+}
+
+/// The subset of a block we need when only looking up its hash
+#[derive(Debug, Deserialize)]
+struct BlockRef {
+    hash: B256,
+    number: U64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TxRef {
+    block_number: Option<U64>,
+}
+
+/// The subset of a receipt needed for matching
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcReceipt {
+    #[serde(rename = "type")]
+    tx_type: U8,
+
+    /// missing on pre-byzantium receipts, which carry a state root instead
+    #[serde(default)]
+    status: Option<U8>,
+
+    cumulative_gas_used: U64,
+    logs: Vec<RpcLog>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcLog {
+    address: Address,
+    topics: Vec<B256>,
+    data: Bytes,
+}
+
+impl RpcSource {
+    /// Creates a new JSON-RPC source
+    /// Fails if the node is serving a different chain than the configured one
+    pub async fn new(chain: &ChainConfig) -> Result<Self> {
+        let config = chain
+            .rpc
+            .as_ref()
+            .ok_or_else(|| eyre!("no rpc config for chain {}", chain.chain_id))?;
+
+        let source = Self {
+            client: reqwest::Client::new(),
+            url: config.url.clone(),
+            batch_size: config.batch_size.max(1),
+            tip: AtomicU64::new(0),
+            cache: Mutex::new(Cache {
+                blocks: Default::default(),
+                order: Default::default(),
+                capacity: config.cache_size,
+            }),
+        };
+
+        let chain_id: U64 = source
+            .call("eth_chainId", json!([]))
+            .await?
+            .ok_or_else(|| eyre!("eth_chainId returned null"))?;
+
+        if chain_id.to::<u64>() != chain.chain_id as u64 {
+            return Err(eyre!(
+                "node at {} is serving chain id {}, but {} was configured",
+                config.url,
+                chain_id,
+                chain.chain_id
+            ));
+        }
+
+        Ok(source)
+    }
+
+    /// Sends all calls in a single batch request
+    /// Results are returned in the same order as the calls
+    async fn batch(&self, calls: &[(&str, Value)]) -> Result<Vec<Result<Option<Value>>>> {
+        let body: Vec<_> = calls
+            .iter()
+            .enumerate()
+            .map(|(id, (method, params))| {
+                json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+            })
+            .collect();
+
+        let responses: Vec<Response> = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let mut results: Vec<_> = calls
+            .iter()
+            .map(|(method, _)| Err(eyre!("no response to {}", method)))
+            .collect();
+
+        for response in responses {
+            let Some(result) = results.get_mut(response.id) else {
+                continue;
+            };
+
+            *result = match response.error {
+                Some(err) => Err(eyre!(
+                    "{} failed: {} ({})",
+                    calls[response.id].0,
+                    err.message,
+                    err.code
+                )),
+                None => Ok(response.result),
+            };
+        }
+
+        Ok(results)
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<Option<T>> {
+        let mut results = self.batch(&[(method, params)]).await?;
+        decode(results.remove(0))
+    }
+
+    /// Fetches a block along with its receipts
+    /// On a cache miss, a whole batch of neighbouring blocks is prefetched: backwards if the
+    /// following block was already fetched (as a backfill would need), forwards otherwise
+    async fn block(&self, number: u64) -> Result<Option<Arc<RpcBlock>>> {
+        let backwards = {
+            let cache = self.cache.lock().unwrap();
+            if let Some(block) = cache.blocks.get(&number) {
+                return Ok(Some(block.clone()));
+            }
+            cache.blocks.contains_key(&(number + 1))
+        };
+
+        let range = if backwards {
+            number.saturating_sub(self.batch_size - 1)..=number
+        } else {
+            let tip = self.tip.load(Ordering::Relaxed).max(number);
+            number..=tip.min(number + self.batch_size - 1)
+        };
+
+        let mut calls: Vec<_> = range
+            .clone()
+            .map(|n| ("eth_getBlockByNumber", json!([U64::from(n), true])))
+            .collect();
+        calls.extend(
+            range
+                .clone()
+                .map(|n| ("eth_getBlockReceipts", json!([U64::from(n)]))),
+        );
+
+        let mut blocks = self.batch(&calls).await?;
+        let receipts = blocks.split_off(calls.len() / 2);
+
+        let mut fetched = Vec::new();
+        for (n, (block, receipts)) in range.zip(blocks.into_iter().zip(receipts)) {
+            // blocks past the tip don't exist yet
+            let Some(block) = decode(block)? else {
+                continue;
+            };
+            let receipts =
+                decode(receipts)?.ok_or_else(|| eyre!("missing receipts for block {}", n))?;
+
+            fetched.push((n, Arc::new(RpcBlock::new(block, receipts)?)));
+        }
+
+        let result = fetched
+            .iter()
+            .find(|(n, _)| *n == number)
+            .map(|(_, block)| block.clone());
+
+        let mut cache = self.cache.lock().unwrap();
+        for (n, block) in fetched {
+            cache.insert(n, block);
+        }
+
+        Ok(result)
+    }
+
+    async fn block_or_err(&self, number: u64) -> Result<Arc<RpcBlock>> {
+        self.block(number)
+            .await?
+            .ok_or_else(|| eyre!("block {} not found", number))
+    }
+}
+
+#[async_trait]
+impl BlockSource for RpcSource {
+    async fn tip(&self) -> Result<u64> {
+        let tip: U64 = self
+            .call("eth_blockNumber", json!([]))
+            .await?
+            .ok_or_else(|| eyre!("eth_blockNumber returned null"))?;
+
+        let tip = tip.to();
+        self.tip.store(tip, Ordering::Relaxed);
+        Ok(tip)
+    }
+
+    async fn header(&self, number: u64) -> Result<Option<Header>> {
+        Ok(self.block(number).await?.map(|b| b.header.clone()))
+    }
+
+    /// Always asks the node, since this is what reorg detection relies on
+    /// Cached blocks that don't build on the returned hash were orphaned, and are dropped
+    async fn block_hash(&self, number: u64) -> Result<Option<B256>> {
+        let hash = self
+            .call::<BlockRef>("eth_getBlockByNumber", json!([U64::from(number), false]))
+            .await?
+            .map(|b| b.hash);
+
+        let mut cache = self.cache.lock().unwrap();
+        let stale = cache
+            .blocks
+            .get(&number)
+            .is_some_and(|b| Some(b.hash) != hash)
+            || cache
+                .blocks
+                .get(&(number + 1))
+                .is_some_and(|b| Some(b.header.parent_hash) != hash);
+
+        if stale {
+            cache.invalidate_from(number);
+        }
+
+        Ok(hash)
+    }
+
+    async fn transactions(&self, number: u64) -> Result<Vec<TransactionSignedNoHash>> {
+        Ok(self.block_or_err(number).await?.transactions.clone())
+    }
+
+    async fn senders(&self, number: u64) -> Result<Vec<Option<Address>>> {
+        Ok(self
+            .block_or_err(number)
+            .await?
+            .senders
+            .iter()
+            .copied()
+            .map(Some)
+            .collect())
+    }
+
+    async fn receipts(&self, number: u64) -> Result<Vec<Option<Receipt>>> {
+        Ok(self
+            .block_or_err(number)
+            .await?
+            .receipts
+            .iter()
+            .cloned()
+            .map(Some)
+            .collect())
+    }
+
+    /// Only archive nodes keep the historical state this needs. Others return an error
+    async fn has_code(&self, address: Address, number: u64) -> Result<Option<bool>> {
+        let code = self
+            .call::<Bytes>("eth_getCode", json!([address, U64::from(number)]))
+            .await?;
+        Ok(code.map(|code| !code.is_empty()))
+    }
+
+    /// Uses the node's `safe` and `finalized` tags where supported
+    /// Otherwise, safe is approximated as `safe_depth` blocks behind the tip, and nothing is
+    /// considered finalized
+    async fn finality(&self, safe_depth: u64) -> Result<(u64, u64)> {
+        let mut results = self
+            .batch(&[
+                ("eth_blockNumber", json!([])),
+                ("eth_getBlockByNumber", json!(["safe", false])),
+                ("eth_getBlockByNumber", json!(["finalized", false])),
+            ])
+            .await?
+            .into_iter();
+
+        let tip: U64 = results
+            .next()
+            .map(decode::<U64>)
+            .transpose()?
+            .flatten()
+            .ok_or_else(|| eyre!("eth_blockNumber returned null"))?;
+        let mut tagged = results.map(|r| {
+            decode::<BlockRef>(r)
+                .ok()
+                .flatten()
+                .map(|b| b.number.to::<u64>())
+        });
+        let (safe, finalized) = (tagged.next().flatten(), tagged.next().flatten());
+
+        let finalized = finalized.unwrap_or(0);
+        let safe = safe
+            .unwrap_or_else(|| tip.to::<u64>().saturating_sub(safe_depth))
+            .max(finalized);
+
+        Ok((safe, finalized))
+    }
+
+    async fn transaction_by_hash(&self, hash: B256) -> Result<Option<TransactionSigned>> {
+        // pending transactions have no block number yet
+        let Some(TxRef {
+            block_number: Some(number),
+        }) = self.call("eth_getTransactionByHash", json!([hash])).await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(self.block(number.to()).await?.and_then(|block| {
+            block
+                .transactions
+                .iter()
+                .find(|tx| tx.hash() == hash)
+                .map(|tx| tx.clone().with_hash())
+        }))
+    }
+}
+
+impl Cache {
+    fn insert(&mut self, number: u64, block: Arc<RpcBlock>) {
+        if self.blocks.insert(number, block).is_none() {
+            self.order.push_back(number);
+        }
+
+        while self.blocks.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => self.blocks.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    /// Drops all blocks from `number` onwards
+    fn invalidate_from(&mut self, number: u64) {
+        self.blocks.retain(|n, _| *n < number);
+        self.order.retain(|n| *n < number);
+    }
+}
+
+impl RpcBlock {
+    fn new(block: reth_rpc_types::Block, receipts: Vec<RpcReceipt>) -> Result<Self> {
+        let senders = match &block.transactions {
+            BlockTransactions::Full(txs) => txs.iter().map(|tx| tx.from).collect(),
+            _ => return Err(eyre!("expected block with full transactions")),
+        };
+        // the node's own hash, since recomputing it from the decoded header drops any fields
+        // alloy doesn't model, e.g. on L2s
+        let hash = block
+            .header
+            .hash
+            .ok_or_else(|| eyre!("block {:?} has no hash", block.header.number))?;
+
+        let block = Block::try_from(block)?;
+        let receipts = receipts
+            .into_iter()
+            .map(RpcReceipt::into_receipt)
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            hash,
+            transactions: block.body.into_iter().map(Into::into).collect(),
+            header: block.header,
+            senders,
+            receipts,
+        })
+    }
+}
+
+impl RpcReceipt {
+    fn into_receipt(self) -> Result<Receipt> {
+        let tx_type = self.tx_type.to::<u8>();
+
+        Ok(Receipt {
+            tx_type: TxType::try_from(tx_type)
+                .map_err(|_| eyre!("unsupported transaction type {}", tx_type))?,
+            success: self.status.map_or(true, |status| status == U8::from(1)),
+            cumulative_gas_used: self.cumulative_gas_used.to(),
+            logs: self
+                .logs
+                .into_iter()
+                .map(|log| Log::new_unchecked(log.address, log.topics, log.data))
+                .collect(),
+        })
+    }
+}
+
+fn decode<T: DeserializeOwned>(result: Result<Option<Value>>) -> Result<Option<T>> {
+    Ok(result?.map(serde_json::from_value).transpose()?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::{
+        config::{Config, RpcConfig},
+        sync::source::{
+            memory::{FixtureTx, InMemorySource},
+            mock_rpc::MockRpc,
+        },
+    };
+
+    async fn connect(source: Arc<InMemorySource>, batch_size: u64) -> Result<(RpcSource, MockRpc)> {
+        let mut chain = Config::for_test().chains[0].clone();
+        let mock = MockRpc::serve(source, chain.chain_id as u64).await?;
+
+        chain.reth = None;
+        chain.rpc = Some(RpcConfig {
+            url: mock.url.clone(),
+            batch_size,
+            cache_size: 100,
+        });
+
+        Ok((RpcSource::new(&chain).await?, mock))
+    }
+
+    fn populate(source: &InMemorySource, blocks: usize) {
+        let (alice, bob) = (Address::repeat_byte(0xa), Address::repeat_byte(0xb));
+        for _ in 0..blocks {
+            source.push_block(vec![
+                FixtureTx::transfer(alice, bob),
+                FixtureTx::erc20_transfer(bob, Address::repeat_byte(0xe), bob, alice),
+            ]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_matches_source() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        populate(&source, 3);
+        let (rpc, _mock) = connect(source.clone(), 10).await?;
+
+        assert_eq!(rpc.tip().await?, 3);
+        for n in 1..=3 {
+            assert_eq!(rpc.header(n).await?, source.header(n).await?);
+            assert_eq!(rpc.block_hash(n).await?, source.block_hash(n).await?);
+            assert_eq!(rpc.transactions(n).await?, source.transactions(n).await?);
+            assert_eq!(rpc.senders(n).await?, source.senders(n).await?);
+            assert_eq!(rpc.receipts(n).await?, source.receipts(n).await?);
+        }
+        assert_eq!(rpc.header(4).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batches_requests() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        populate(&source, 8);
+        let (rpc, mock) = connect(source.clone(), 4).await?;
+        rpc.tip().await?;
+        let before = mock.requests.load(Ordering::SeqCst);
+
+        // walking backwards from the tip, as a backfill does
+        for n in (1..=8).rev() {
+            rpc.header(n).await?;
+            rpc.transactions(n).await?;
+            rpc.receipts(n).await?;
+        }
+
+        // block 8 alone, then [4, 7], then [0, 3]
+        assert_eq!(mock.requests.load(Ordering::SeqCst) - before, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reorg_invalidates_cache() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        populate(&source, 3);
+        let (rpc, _mock) = connect(source.clone(), 10).await?;
+        rpc.tip().await?;
+        let orphaned = rpc.header(2).await?;
+
+        source.reorg(1);
+        populate(&source, 2);
+
+        assert_eq!(rpc.block_hash(2).await?, source.block_hash(2).await?);
+        assert_ne!(rpc.header(2).await?, orphaned);
+        assert_eq!(rpc.header(2).await?, source.header(2).await?);
+        assert_eq!(rpc.header(3).await?, source.header(3).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transaction_by_hash() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        populate(&source, 2);
+        let (rpc, _mock) = connect(source.clone(), 10).await?;
+
+        let tx = source.transactions(2).await?.remove(1).with_hash();
+        assert_eq!(rpc.transaction_by_hash(tx.hash()).await?, Some(tx));
+        assert_eq!(rpc.transaction_by_hash(B256::ZERO).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chain_id_mismatch() -> Result<()> {
+        let source = Arc::new(InMemorySource::default());
+        let mock = MockRpc::serve(source, 1).await?;
+
+        let mut chain = Config::for_test().chains[0].clone();
+        chain.reth = None;
+        chain.rpc = Some(RpcConfig {
+            url: mock.url,
+            batch_size: 10,
+            cache_size: 100,
+        });
+
+        assert!(RpcSource::new(&chain).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+    select,
+    sync::{mpsc, watch},
+    time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
+
+use super::BlockSource;
+use crate::config::{ChainConfig, SyncConfig};
+
+/// While the source notifies of new blocks, it is still polled this often, in case a notification
+/// is missed
+const NOTIFIED_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long to wait for further writes to reth's datadir, once one is seen, before reading the tip
+/// A single block is written as several files and pages
+const WRITE_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Bounds of the exponential backoff between attempts to reconnect to reth's IPC socket
+const IPC_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const IPC_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Watches a chain's block source for new blocks, and publishes its tip to every subscribed worker
+///
+/// New blocks are noticed, in order of preference:
+///   - through a `newHeads` subscription on reth's IPC socket, reconnecting whenever reth restarts
+///   - by watching reth's datadir for writes, coalescing those that come in quick succession
+///   - by polling the source every `tip_poll_ms`
+///
+/// Whenever one of these fires, the tip is read from the source itself, so that it only ever
+/// includes complete blocks
+#[derive(Debug)]
+pub struct TipWatcher {
+    chain_id: i32,
+    source: Arc<dyn BlockSource>,
+    ipc: Option<PathBuf>,
+    paths: Vec<PathBuf>,
+    poll_interval: Duration,
+    tx: watch::Sender<u64>,
+    token: CancellationToken,
+}
+
+impl TipWatcher {
+    pub fn new(
+        chain: &ChainConfig,
+        config: &SyncConfig,
+        source: Arc<dyn BlockSource>,
+        token: CancellationToken,
+    ) -> Self {
+        let (ipc, paths) = match &chain.reth {
+            Some(reth) => (
+                reth.ipc.clone(),
+                vec![reth.db.clone(), reth.static_files.clone()],
+            ),
+            None => (None, vec![]),
+        };
+
+        Self {
+            chain_id: chain.chain_id,
+            source,
+            ipc,
+            paths,
+            poll_interval: Duration::from_millis(config.tip_poll_ms.max(1)),
+            tx: watch::channel(0).0,
+            token,
+        }
+    }
+
+    /// A receiver of every new tip, starting at 0 until the first one is read
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.tx.subscribe()
+    }
+
+    #[instrument(name = "tip", skip(self), fields(chain_id = self.chain_id))]
+    pub async fn run(self) -> Result<()> {
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+
+        let subscribed = Arc::new(AtomicBool::new(false));
+        let ipc = self
+            .ipc
+            .clone()
+            .map(|path| tokio::spawn(subscribe_ipc(path, events_tx.clone(), subscribed.clone())));
+        let files = match ipc {
+            Some(_) => None,
+            None => self.watch_files(events_tx.clone()),
+        };
+
+        loop {
+            match self.source.tip().await {
+                Ok(tip) => {
+                    self.tx.send_if_modified(|current| {
+                        let modified = *current != tip;
+                        *current = tip;
+                        modified
+                    });
+                }
+                Err(err) => warn!(event = "tip", error = %err),
+            }
+
+            // the source is polled as usual while reconnecting to reth
+            let notified = match &ipc {
+                Some(_) => subscribed.load(Ordering::Relaxed),
+                None => files.is_some(),
+            };
+            let interval = match notified {
+                true => NOTIFIED_POLL_INTERVAL,
+                false => self.poll_interval,
+            };
+
+            select! {
+                _ = self.token.cancelled() => break,
+                _ = events_rx.recv() => {
+                    if files.is_some() {
+                        select! {
+                            _ = self.token.cancelled() => break,
+                            _ = sleep(WRITE_DEBOUNCE) => {}
+                        }
+                    }
+                }
+                _ = sleep(interval) => {}
+            }
+
+            // a single block usually comes with several notifications
+            while events_rx.try_recv().is_ok() {}
+        }
+
+        if let Some(handle) = ipc {
+            handle.abort();
+        }
+
+        Ok(())
+    }
+
+    /// Watches reth's datadir, notifying `events` of every write to it
+    /// `None` if there is nothing to watch, or it can't be watched, in which case the source is
+    /// polled instead
+    fn watch_files(&self, events: mpsc::UnboundedSender<()>) -> Option<RecommendedWatcher> {
+        if self.paths.is_empty() {
+            return None;
+        }
+
+        let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if res.is_ok_and(|event| event.kind.is_modify() || event.kind.is_create()) {
+                let _ = events.send(());
+            }
+        })
+        .and_then(|mut watcher| {
+            for path in self.paths.iter() {
+                watcher.watch(path, RecursiveMode::NonRecursive)?;
+            }
+            Ok(watcher)
+        });
+
+        match watcher {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                warn!(event = "watch", error = %err, "falling back to polling");
+                None
+            }
+        }
+    }
+}
+
+/// Keeps a `newHeads` subscription open on reth's IPC socket, notifying `events` of every new head
+/// Whenever the connection fails or closes, e.g. because reth restarted, it is reopened with
+/// exponential backoff. `subscribed` is set while the subscription is active
+async fn subscribe_ipc(
+    path: PathBuf,
+    events: mpsc::UnboundedSender<()>,
+    subscribed: Arc<AtomicBool>,
+) {
+    let mut backoff = IPC_MIN_BACKOFF;
+    loop {
+        let res = subscribe_ipc_once(&path, &events, &subscribed).await;
+        if events.is_closed() {
+            return;
+        }
+
+        // a subscription that got through starts the backoff over
+        if subscribed.swap(false, Ordering::Relaxed) {
+            backoff = IPC_MIN_BACKOFF;
+        }
+        if let Err(err) = res {
+            warn!(event = "subscribe", error = %err, retry_in = ?backoff, "falling back to polling");
+        }
+
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(IPC_MAX_BACKOFF);
+    }
+}
+
+/// A single `newHeads` subscription, until it fails or the connection closes
+async fn subscribe_ipc_once(
+    path: &Path,
+    events: &mpsc::UnboundedSender<()>,
+    subscribed: &AtomicBool,
+) -> Result<()> {
+    let mut stream = UnixStream::connect(path).await?;
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_subscribe",
+        "params": ["newHeads"],
+    });
+    stream.write_all(request.to_string().as_bytes()).await?;
+
+    // messages are sent back to back, so they're split by parsing them
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(eyre!("IPC connection closed"));
+        }
+        buf.extend_from_slice(&chunk[..read]);
+
+        let mut messages = serde_json::Deserializer::from_slice(&buf).into_iter::<Value>();
+        for message in messages.by_ref() {
+            let message = match message {
+                Ok(message) => message,
+                Err(err) if err.is_eof() => break,
+                Err(err) => return Err(err.into()),
+            };
+
+            if let Some(err) = message.get("error") {
+                return Err(eyre!("newHeads subscription failed: {}", err));
+            }
+            if message["id"] == 1 && message.get("result").is_some() {
+                info!(event = "subscribe", ipc = ?path);
+                subscribed.store(true, Ordering::Relaxed);
+            }
+            if message["method"] == "eth_subscription" {
+                events.send(())?;
+            }
+        }
+        let parsed = messages.byte_offset();
+        buf.drain(..parsed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{config::Config, sync::source::memory::InMemorySource};
+
+    #[tokio::test]
+    async fn test_publishes_new_tips() -> Result<()> {
+        let config = Config::for_test();
+        let chain = ChainConfig {
+            reth: None,
+            ..config.chains[0].clone()
+        };
+        let source = Arc::new(InMemorySource::default());
+        source.push_block(vec![]);
+
+        let token = CancellationToken::new();
+        let watcher = TipWatcher::new(&chain, &config.sync, source.clone(), token.clone());
+        let mut tips = watcher.subscribe();
+        let handle = tokio::spawn(watcher.run());
+
+        tips.changed().await?;
+        assert_eq!(*tips.borrow_and_update(), 1);
+
+        source.push_block(vec![]);
+        tips.changed().await?;
+        assert_eq!(*tips.borrow_and_update(), 2);
+
+        token.cancel();
+        handle.await??;
+
+        Ok(())
+    }
+}